@@ -0,0 +1,183 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
+
+use crate::{Decoder, Error, Format, Limits};
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Input received from the async reader and not yet decoded.
+struct InputBuffer {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl InputBuffer {
+    fn extend(&mut self, data: &[u8]) {
+        if self.pos > self.data.len() / 2 {
+            self.data.drain(..self.pos);
+            self.pos = 0;
+        }
+        self.data.extend_from_slice(data);
+    }
+}
+
+impl Read for InputBuffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for InputBuffer {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Asynchronous counterpart of `Decoder`, reading compressed data from an
+/// `AsyncBufRead`. Runs the same decoding steps once enough input is buffered
+/// for them, so the end of the data may only be decoded at the end of the input.
+pub struct AsyncDecoder<R> {
+    input: R,
+    decoder: Decoder<InputBuffer>,
+    is_eof: bool,
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncDecoder<R> {
+    pub fn new(input: R, format: Format) -> Self {
+        let buffer = InputBuffer {
+            data: Vec::new(),
+            pos: 0,
+        };
+        Self {
+            input,
+            decoder: Decoder::new(buffer, format),
+            is_eof: false,
+        }
+    }
+
+    /// See `Decoder::set_dictionary`.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.decoder.set_dictionary(dictionary);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.decoder.set_limits(limits);
+    }
+
+    /// Attach the position to an error returned through `AsyncRead`.
+    pub fn locate(&mut self, err: io::Error) -> Error {
+        self.decoder.locate(err)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> AsyncRead for AsyncDecoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.decoder.has_output() {
+                let len = this.decoder.read_output(buf.initialize_unfilled())?;
+                buf.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+            let wanted = match this.decoder.step_size(buf.remaining()) {
+                Some(wanted) => wanted,
+                None if this.is_eof => buf.remaining(),
+                None => {
+                    let data = ready!(Pin::new(&mut this.input).poll_fill_buf(cx))?;
+                    let len = data.len();
+                    this.is_eof = len == 0;
+                    this.decoder.get_mut().extend(data);
+                    Pin::new(&mut this.input).consume(len);
+                    continue;
+                }
+            };
+            this.decoder.advance(wanted)?;
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_with_format;
+    use crate::error::ErrorKind;
+    use crate::test_util::sample_data;
+    use anyhow::Result;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    async fn decode_duplex(compressed: Vec<u8>, format: Format) -> io::Result<Vec<u8>> {
+        let (mut writer, reader) = tokio::io::duplex(64);
+        let sender = tokio::spawn(async move {
+            for chunk in compressed.chunks(100) {
+                writer.write_all(chunk).await?;
+                tokio::task::yield_now().await;
+            }
+            writer.shutdown().await
+        });
+        let mut decoder = AsyncDecoder::new(BufReader::with_capacity(37, reader), format);
+        let mut output = Vec::new();
+        let result = decoder.read_to_end(&mut output).await;
+        sender.await??;
+        result.map(|_| output)
+    }
+
+    #[tokio::test]
+    async fn duplex() -> Result<()> {
+        let data = sample_data(300000);
+        for format in [Format::Gzip, Format::Zlib, Format::Raw] {
+            let mut compressed = Vec::new();
+            compress_with_format(data.as_slice(), &mut compressed, 6, format)?;
+            if format == Format::Gzip {
+                compress_with_format(&data[..1000], &mut compressed, 1, format)?;
+            }
+            let output = decode_duplex(compressed, format).await?;
+            let expected_len = match format {
+                Format::Gzip => data.len() + 1000,
+                _ => data.len(),
+            };
+            assert_eq!(output.len(), expected_len);
+            assert!(output[..data.len()] == data);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn truncated() -> Result<()> {
+        let mut compressed = Vec::new();
+        compress_with_format(&b"truncated"[..], &mut compressed, 6, Format::Gzip)?;
+        compressed.truncate(compressed.len() - 3);
+        let mut decoder = AsyncDecoder::new(compressed.as_slice(), Format::Gzip);
+        let err = decoder.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(matches!(
+            decoder.locate(err).kind(),
+            ErrorKind::TruncatedInput
+        ));
+        Ok(())
+    }
+}
@@ -12,6 +12,8 @@ pub struct BitSequence {
 }
 
 impl BitSequence {
+    /// Panics if `len` is greater than 16, so lengths taken from the input must
+    /// be checked before.
     pub fn new(bits: u16, len: u8) -> Self {
         // NB: make sure to zero unused bits so that Eq and Hash work as expected.
         let mut right_data = bits;
@@ -48,6 +50,15 @@ impl BitSequence {
         }
     }
 
+    /// Reverse the order of the bits, e.g. to turn a Huffman code into
+    /// the order it is stored in the stream.
+    pub fn reverse(self) -> Self {
+        match self.len {
+            0 => self,
+            len => Self::new(self.bits.reverse_bits() >> (16 - len), len),
+        }
+    }
+
     pub fn shrink(&mut self, len: u8) -> Self {
         if len > self.len {
             panic!("shrink big len");
@@ -74,43 +85,67 @@ impl<T: BufRead> BitReader<T> {
         }
     }
 
-    pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
+    /// Return up to `len` next bits without consuming them. Fewer bits are returned
+    /// only if the stream ends or does not have enough bytes buffered.
+    pub fn peek_bits(&mut self, len: u8) -> io::Result<BitSequence> {
         if len > 16 {
-            panic!("We can read at most 16 bits at time");
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "We can read at most 16 bits at time",
+            ));
         }
         if self.buf.len() >= len {
-            return Ok(self.buf.shrink(len));
+            return Ok(BitSequence::new(self.buf.bits(), len));
         }
-        if self.buf.len() + 8 < len {
-            let mut read_bytes: [u8; 2] = [0, 0];
-            match self.stream.read_exact(&mut read_bytes) {
-                Ok(()) => {
-                    let value = (read_bytes[0] as u16) + (256_u16) * (read_bytes[1] as u16);
-                    let tail = BitSequence::new(value, len - self.buf.len());
-                    let mut new_buf = BitSequence::new(
-                        value >> (len - self.buf.len()),
-                        16 - (len - self.buf.len()),
-                    );
-                    std::mem::swap(&mut new_buf, &mut self.buf);
-                    Ok(new_buf.concat(tail))
-                }
-                Err(err) => Err(err),
+        let mut value = self.buf.bits() as u32;
+        let mut available = self.buf.len();
+        for &byte in self.stream.fill_buf()? {
+            if available >= len {
+                break;
             }
-        } else {
-            let mut read_byte: [u8; 1] = [0];
-            match self.stream.read_exact(&mut read_byte) {
-                Ok(()) => {
-                    let tail = BitSequence::new(read_byte[0] as u16, len - self.buf.len());
-                    let mut new_buf = BitSequence::new(
-                        read_byte[0] as u16 >> (len - self.buf.len()),
-                        8 - (len - self.buf.len()),
-                    );
-                    std::mem::swap(&mut new_buf, &mut self.buf);
-                    Ok(new_buf.concat(tail))
-                }
-                Err(err) => Err(err),
+            value |= (byte as u32) << available;
+            available += 8;
+        }
+        let available = std::cmp::min(available, len);
+        Ok(BitSequence::new(
+            (value & ((1 << available) - 1)) as u16,
+            available,
+        ))
+    }
+
+    /// Skip `len` bits, which must have been returned by `peek_bits` before.
+    pub fn consume(&mut self, len: u8) -> io::Result<()> {
+        if self.buf.len() >= len {
+            self.buf.shrink(len);
+            return Ok(());
+        }
+        let rest = len - self.buf.len();
+        let (whole, partial) = ((rest / 8) as usize, rest % 8);
+        self.buf = BitSequence::new(0, 0);
+        if partial == 0 {
+            self.stream.consume(whole);
+            return Ok(());
+        }
+        let byte = match self.stream.fill_buf()?.get(whole) {
+            Some(&byte) => byte,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        self.stream.consume(whole + 1);
+        self.buf = BitSequence::new(byte as u16 >> partial, 8 - partial);
+        Ok(())
+    }
+
+    pub fn read_bits(&mut self, len: u8) -> io::Result<BitSequence> {
+        let mut result = BitSequence::new(0, 0);
+        while result.len() < len {
+            let seq = self.peek_bits(len - result.len())?;
+            if seq.len() == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
             }
+            self.consume(seq.len())?;
+            result = result.concat(seq);
         }
+        Ok(result)
     }
 
     /// Discard all the unread bits in the current byte and return a mutable reference
@@ -120,6 +155,24 @@ impl<T: BufRead> BitReader<T> {
         self.buf.len = 0;
         &mut self.stream
     }
+
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Mutable access to the underlying reader, which must not be read from.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+
+    /// Number of the unread bits left from the last byte taken from the stream.
+    pub fn buffered_bits(&self) -> u8 {
+        self.buf.len()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -146,6 +199,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn peek_bits() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(data);
+        assert_eq!(reader.peek_bits(3)?, BitSequence::new(0b011, 3));
+        reader.consume(2)?;
+        assert_eq!(
+            reader.peek_bits(16)?,
+            BitSequence::new(0b1111011011011000, 16)
+        );
+        reader.consume(14)?;
+        assert_eq!(reader.peek_bits(16)?, BitSequence::new(0b10101111, 8));
+        reader.consume(8)?;
+        assert_eq!(reader.peek_bits(1)?, BitSequence::new(0, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn read_bits_across_buffers() -> io::Result<()> {
+        let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
+        let mut reader = BitReader::new(io::BufReader::with_capacity(1, data));
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        assert_eq!(
+            reader.read_bits(16)?,
+            BitSequence::new(0b1111101101101100, 16)
+        );
+        assert_eq!(reader.read_bits(5)?, BitSequence::new(0b10101, 5));
+        Ok(())
+    }
+
     #[test]
     fn borrow_reader_from_boundary() -> io::Result<()> {
         let data: &[u8] = &[0b01100011, 0b11011011, 0b10101111];
@@ -0,0 +1,90 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, Write};
+
+use crate::bit_reader::BitSequence;
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct BitWriter<T> {
+    stream: T,
+    buf: u64,
+    len: u8,
+}
+
+impl<T: Write> BitWriter<T> {
+    pub fn new(stream: T) -> Self {
+        Self {
+            stream,
+            buf: 0,
+            len: 0,
+        }
+    }
+
+    pub fn write_bits(&mut self, seq: BitSequence) -> io::Result<()> {
+        self.buf |= (seq.bits() as u64) << self.len;
+        self.len += seq.len();
+        if self.len >= 32 {
+            let bytes = (self.buf as u32).to_le_bytes();
+            self.stream.write_all(&bytes)?;
+            self.buf >>= 32;
+            self.len -= 32;
+        }
+        Ok(())
+    }
+
+    /// Pad the current byte with zero bits and return a mutable reference
+    /// to the underlying writer.
+    pub fn borrow_writer_from_boundary(&mut self) -> io::Result<&mut T> {
+        let byte_count = (self.len as usize).div_ceil(8);
+        let bytes = self.buf.to_le_bytes();
+        self.stream.write_all(&bytes[..byte_count])?;
+        self.buf = 0;
+        self.len = 0;
+        Ok(&mut self.stream)
+    }
+
+    pub fn finish(mut self) -> io::Result<T> {
+        self.borrow_writer_from_boundary()?;
+        Ok(self.stream)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitReader;
+
+    #[test]
+    fn write_bits() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b1, 1))?;
+        writer.write_bits(BitSequence::new(0b01, 2))?;
+        writer.write_bits(BitSequence::new(0b100, 3))?;
+        writer.write_bits(BitSequence::new(0b1101, 4))?;
+        writer.write_bits(BitSequence::new(0b10110, 5))?;
+        writer.write_bits(BitSequence::new(0b01011111, 8))?;
+        assert_eq!(writer.finish()?, vec![0b01100011, 0b11011011, 0b00101111]);
+        Ok(())
+    }
+
+    #[test]
+    fn borrow_writer_from_boundary() -> io::Result<()> {
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        writer
+            .borrow_writer_from_boundary()?
+            .write_all(&[0b11011011])?;
+        writer.write_bits(BitSequence::new(0b101011110101, 12))?;
+        let data = writer.finish()?;
+
+        let mut reader = BitReader::new(data.as_slice());
+        assert_eq!(reader.read_bits(3)?, BitSequence::new(0b011, 3));
+        assert_eq!(reader.read_bits(5)?, BitSequence::new(0, 5));
+        assert_eq!(reader.read_bits(8)?, BitSequence::new(0b11011011, 8));
+        assert_eq!(reader.read_bits(12)?, BitSequence::new(0b101011110101, 12));
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+#![forbid(unsafe_code)]
+
+use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
+////////////////////////////////////////////////////////////////////////////////
+
+static CRC_CHECKER: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const ADLER_MOD: u32 = 65521;
+// The largest n such that 255 * n * (n + 1) / 2 + (n + 1) * (ADLER_MOD - 1) fits in u32.
+const ADLER_NMAX: usize = 5552;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A checksum of the decompressed data, computed incrementally.
+pub trait Checksum: Send {
+    fn update(&mut self, data: &[u8]);
+
+    /// Return the checksum of the data so far and start over.
+    fn finish(&mut self) -> u32;
+
+    fn reset(&mut self) {
+        self.finish();
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// CRC-32 as used by gzip and zip, see RFC 1952, section 8.
+pub struct Crc32(Digest<'static, u32>);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(CRC_CHECKER.digest())
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Crc32 {
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finish(&mut self) -> u32 {
+        std::mem::take(self).0.finalize()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Adler-32 checksum, see RFC 1950, section 8.2.
+#[derive(Clone, Copy, Debug)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self { a: 1, b: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(ADLER_NMAX) {
+            for &byte in chunk {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= ADLER_MOD;
+            self.b %= ADLER_MOD;
+        }
+    }
+
+    pub fn finalize(self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Adler32 {
+    fn update(&mut self, data: &[u8]) {
+        Adler32::update(self, data);
+    }
+
+    fn finish(&mut self) -> u32 {
+        std::mem::take(self).finalize()
+    }
+}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.finalize()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumKind {
+    Crc32,
+    Adler32,
+}
+
+impl ChecksumKind {
+    pub fn new_checksum(self) -> Box<dyn Checksum> {
+        match self {
+            Self::Crc32 => Box::new(Crc32::new()),
+            Self::Adler32 => Box::new(Adler32::new()),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adler32_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+
+        let data = vec![0xff_u8; 100000];
+        let mut adler = Adler32::new();
+        for chunk in data.chunks(777) {
+            adler.update(chunk);
+        }
+        assert_eq!(adler.finalize(), adler32(&data));
+        assert_eq!(adler32(&data), 0x149a302c);
+    }
+
+    #[test]
+    fn finish_starts_over() {
+        for kind in [ChecksumKind::Crc32, ChecksumKind::Adler32] {
+            let mut checksum = kind.new_checksum();
+            checksum.update(b"first");
+            checksum.finish();
+            checksum.update(b"Wikipedia");
+            let value = checksum.finish();
+            match kind {
+                ChecksumKind::Crc32 => assert_eq!(value, 0xadaac02e),
+                ChecksumKind::Adler32 => assert_eq!(value, 0x11E60398),
+            }
+        }
+    }
+}
@@ -0,0 +1,1218 @@
+#![forbid(unsafe_code)]
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Read, Write};
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
+use log::debug;
+
+use crate::bit_reader::BitReader;
+use crate::checksum::{adler32, ChecksumKind};
+use crate::deflate::{BlockDecoder, CompressionType, Deflate, DeflateReader};
+use crate::error::{Error, ErrorKind, Limit};
+#[cfg(feature = "tokio")]
+use crate::gzip;
+use crate::gzip::{GzipReader, MemberFooter, MemberHeader};
+use crate::huffman_coding::{read_code_lengths, DistanceToken, HuffmanCoding, LitLenToken};
+use crate::index::Checkpoint;
+use crate::tracking_writer::TrackingWriter;
+use crate::zlib::{self, ZlibHeader};
+use crate::Format;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const STORED_CHUNK_SIZE: usize = 1 << 16;
+// Upper bound of the size of a block header with the code lengths.
+#[cfg(feature = "tokio")]
+const MAX_BLOCK_HEADER_LEN: usize = 600;
+// A literal takes at most 15 bits, a match of at least 3 bytes at most 48 bits
+// (60 bits with Deflate64).
+#[cfg(feature = "tokio")]
+const MAX_INPUT_PER_BYTE: usize = 2;
+#[cfg(feature = "tokio")]
+const MAX_SYMBOL_LEN: usize = 8;
+// The compression ratio is only checked after this much output, as the ratio of
+// a short prefix says little about the whole stream.
+pub(crate) const MIN_RATIO_CHECK_OUTPUT: u64 = 1 << 20;
+
+enum State {
+    Header,
+    Block,
+    Stored(usize),
+    Compressed(Box<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)>),
+    Footer,
+    Done,
+}
+
+/// Decode symbols until the end of the block or until `writer` has produced `limit`
+/// bytes of the member, appending them to `tokens` if given. Returns whether the
+/// end of the block was reached.
+fn decode_symbols<R: BufRead, W: Write>(
+    reader: &mut BitReader<R>,
+    writer: &mut TrackingWriter<W>,
+    lit_len: &HuffmanCoding<LitLenToken>,
+    distance: &HuffmanCoding<DistanceToken>,
+    limit: usize,
+    mut tokens: Option<&mut Vec<BlockEvent>>,
+) -> Result<bool> {
+    while writer.byte_count() < limit {
+        match lit_len.read_symbol(reader)? {
+            LitLenToken::Literal(val) => {
+                writer.write_all(&[val])?;
+                if let Some(tokens) = &mut tokens {
+                    tokens.push(BlockEvent::Literal(val));
+                }
+            }
+            LitLenToken::EndOfBlock => {
+                return Ok(true);
+            }
+            LitLenToken::Length { base, extra_bits } => {
+                let len = base as u32 + reader.read_bits(extra_bits)?.bits() as u32;
+                let dist_token = distance.read_symbol(reader)?;
+                let dist =
+                    dist_token.base as u32 + reader.read_bits(dist_token.extra_bits)?.bits() as u32;
+                if let Some(tokens) = &mut tokens {
+                    tokens.push(BlockEvent::Match {
+                        length: len,
+                        distance: dist,
+                    });
+                }
+                writer.write_previous(dist as usize, len as usize)?;
+            }
+        }
+    }
+    Ok(false)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Counts the bytes consumed from the underlying reader.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+/// Metadata of a decoded gzip member.
+#[derive(Clone, Debug)]
+pub struct MemberInfo {
+    pub header: MemberHeader,
+    pub footer: MemberFooter,
+    /// Offset of the member in the compressed input.
+    pub offset: u64,
+    /// Size of the member including its header and footer.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+/// A deflate block header, see `Decoder::record_blocks`.
+#[derive(Clone, Debug)]
+pub struct BlockInfo {
+    /// Index of the gzip member (always 0 for zlib and raw deflate data).
+    pub member: usize,
+    /// Position of the block header in the compressed input, in bits.
+    pub bit_offset: u64,
+    /// Decompressed size of the data before the block.
+    pub uncompressed_offset: u64,
+    pub is_final: bool,
+    pub compression_type: CompressionType,
+    /// Code lengths of the litlen and distance trees of dynamic blocks.
+    pub litlen_lengths: Vec<u8>,
+    pub distance_lengths: Vec<u8>,
+    /// Length of stored blocks.
+    pub stored_len: Option<u16>,
+}
+
+/// Structure of the decoded deflate data, in stream order.
+#[derive(Clone, Debug)]
+pub enum BlockEvent {
+    Block(BlockInfo),
+    Literal(u8),
+    Match {
+        length: u32,
+        distance: u32,
+    },
+    /// The end of a block, with the position after it in bits.
+    EndOfBlock {
+        bit_offset: u64,
+    },
+}
+
+/// Limits enforced while decoding untrusted input, none by default. Exceeding
+/// one fails with `ErrorKind::LimitExceeded`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Total decompressed size; the output is cut at the limit.
+    pub max_output: Option<u64>,
+    /// Decompressed size per compressed byte, checked after the first MiB of output.
+    pub max_ratio: Option<u64>,
+    /// Number of gzip members.
+    pub max_members: Option<usize>,
+    /// Length of the FEXTRA, FNAME and FCOMMENT header fields.
+    pub max_header_field: Option<usize>,
+}
+
+/// Position of the decoding, see `Decoder::set_progress`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Compressed bytes consumed from the input.
+    pub compressed: u64,
+    /// Decompressed bytes produced, including those not read yet.
+    pub uncompressed: u64,
+    /// Index of the current gzip member (always 0 for zlib and raw deflate data).
+    pub member: usize,
+}
+
+type ProgressCallback = Box<dyn FnMut(&Progress) + Send>;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Streaming decompressor: reads compressed data in the given `Format` from `R`
+/// and yields the decompressed contents (of all the members, for gzip) through `Read`.
+pub struct Decoder<R> {
+    deflate_reader: DeflateReader<CountingReader<R>>,
+    writer: TrackingWriter<VecDeque<u8>>,
+    block_decoder: Box<dyn BlockDecoder>,
+    state: State,
+    is_final_block: bool,
+    format: Format,
+    dictionary: Option<Vec<u8>>,
+    limits: Limits,
+    member: Option<(u64, MemberHeader)>,
+    members: Option<Vec<MemberInfo>>,
+    member_index: usize,
+    blocks: Option<Vec<BlockEvent>>,
+    record_tokens: bool,
+    // Decompressed size of the previous members.
+    output_base: u64,
+    checkpoint_spacing: Option<u64>,
+    checkpoints: Vec<Checkpoint>,
+    // The current member was resumed from a checkpoint, so its footer cannot be verified.
+    is_resumed: bool,
+    // An exceeded limit, reported once the output up to it is read.
+    limit_error: Option<Error>,
+    progress: Option<ProgressCallback>,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(input: R, format: Format) -> Self {
+        Self::with_block_decoder(input, format, Box::new(Deflate))
+    }
+
+    /// Decode the blocks with the given deflate variant, e.g. `Deflate64`.
+    pub fn with_block_decoder(
+        input: R,
+        format: Format,
+        block_decoder: Box<dyn BlockDecoder>,
+    ) -> Self {
+        let checksum = match format {
+            Format::Zlib => ChecksumKind::Adler32,
+            _ => ChecksumKind::Crc32,
+        };
+        let writer = TrackingWriter::with_checksum(
+            VecDeque::new(),
+            checksum.new_checksum(),
+            block_decoder.history_size(),
+        );
+        Self {
+            deflate_reader: DeflateReader::new(BitReader::new(CountingReader {
+                inner: input,
+                count: 0,
+            })),
+            writer,
+            block_decoder,
+            state: State::Header,
+            is_final_block: false,
+            format,
+            dictionary: None,
+            limits: Limits::default(),
+            member: None,
+            members: None,
+            member_index: 0,
+            blocks: None,
+            record_tokens: false,
+            output_base: 0,
+            checkpoint_spacing: None,
+            checkpoints: Vec::new(),
+            is_resumed: false,
+            limit_error: None,
+            progress: None,
+        }
+    }
+
+    /// Continue after `members` members decoded separately from `input_size`
+    /// bytes into `output_size` bytes, which count for the limits and positions.
+    pub(crate) fn set_preceding(&mut self, members: usize, input_size: u64, output_size: u64) {
+        self.member_index = members;
+        self.deflate_reader.bit_reader().get_mut().count = input_size;
+        self.output_base = output_size;
+    }
+
+    /// Continue decoding at a checkpoint. `input` must be positioned at the byte
+    /// containing `checkpoint.bit_offset`.
+    pub(crate) fn resume(input: R, format: Format, checkpoint: &Checkpoint) -> io::Result<Self> {
+        let mut decoder = Self::new(input, format);
+        let bit_reader = decoder.deflate_reader.bit_reader();
+        bit_reader.read_bits((checkpoint.bit_offset % 8) as u8)?;
+        decoder.writer.preload_history(&checkpoint.window);
+        decoder.output_base = checkpoint.uncompressed_offset;
+        decoder.state = State::Block;
+        decoder.is_resumed = true;
+        Ok(decoder)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.deflate_reader.into_inner().inner
+    }
+
+    /// Mutable access to the input, which must not be read from.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.deflate_reader.bit_reader().get_mut().inner
+    }
+
+    /// Set the preset dictionary for zlib streams with FDICT, or for raw deflate data.
+    pub fn set_dictionary(&mut self, dictionary: &[u8]) {
+        self.dictionary = Some(dictionary.to_vec());
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Call `callback` with the position after each decoding step, that is
+    /// after each block header and each chunk of decoded data.
+    pub fn set_progress(&mut self, callback: impl FnMut(&Progress) + Send + 'static) {
+        self.progress = Some(Box::new(callback));
+    }
+
+    /// The position of the decoding so far.
+    pub fn progress(&mut self) -> Progress {
+        Progress {
+            compressed: self.deflate_reader.bit_reader().get_ref().count,
+            uncompressed: self.output_position(),
+            member: self.member_index,
+        }
+    }
+
+    /// Start recording the metadata of decoded gzip members, see `take_members`.
+    pub fn record_members(&mut self) {
+        self.members.get_or_insert_with(Vec::new);
+    }
+
+    /// Take the members decoded since the previous call. Empty unless
+    /// `record_members` was called.
+    pub fn take_members(&mut self) -> Vec<MemberInfo> {
+        match &mut self.members {
+            Some(members) => std::mem::take(members),
+            None => Vec::new(),
+        }
+    }
+
+    /// Start recording the headers of the deflate blocks, and their literals and
+    /// matches if `with_tokens`, see `take_blocks`.
+    pub fn record_blocks(&mut self, with_tokens: bool) {
+        self.blocks.get_or_insert_with(Vec::new);
+        self.record_tokens = with_tokens;
+    }
+
+    /// Take the events decoded since the previous call. Empty unless
+    /// `record_blocks` was called.
+    pub fn take_blocks(&mut self) -> Vec<BlockEvent> {
+        match &mut self.blocks {
+            Some(blocks) => std::mem::take(blocks),
+            None => Vec::new(),
+        }
+    }
+
+    fn bit_position(&mut self) -> u64 {
+        let bit_reader = self.deflate_reader.bit_reader();
+        bit_reader.get_ref().count * 8 - bit_reader.buffered_bits() as u64
+    }
+
+    fn record_end_of_block(&mut self) {
+        let bit_offset = self.bit_position();
+        if let Some(blocks) = &mut self.blocks {
+            blocks.push(BlockEvent::EndOfBlock { bit_offset });
+        }
+    }
+
+    /// Start recording checkpoints at block boundaries, at least `spacing`
+    /// decompressed bytes apart, see `take_checkpoints`.
+    pub(crate) fn record_checkpoints(&mut self, spacing: u64) {
+        self.checkpoint_spacing = Some(spacing);
+    }
+
+    pub(crate) fn take_checkpoints(&mut self) -> Vec<Checkpoint> {
+        std::mem::take(&mut self.checkpoints)
+    }
+
+    fn output_position(&self) -> u64 {
+        self.output_base + self.writer.byte_count() as u64
+    }
+
+    fn record_checkpoint(&mut self) {
+        let spacing = match self.checkpoint_spacing {
+            Some(spacing) => spacing,
+            None => return,
+        };
+        let position = self.output_position();
+        let last = self
+            .checkpoints
+            .last()
+            .map_or(0, |last| last.uncompressed_offset);
+        if position < last + spacing {
+            return;
+        }
+        let bit_offset = self.bit_position();
+        debug!("checkpoint at {} (bit {})", position, bit_offset);
+        self.checkpoints.push(Checkpoint {
+            uncompressed_offset: position,
+            bit_offset,
+            window: self.writer.history(),
+        });
+    }
+
+    /// Advance the decoding, producing up to about `wanted` bytes.
+    fn step(&mut self, wanted: usize) -> Result<()> {
+        match std::mem::replace(&mut self.state, State::Done) {
+            State::Header => {
+                let reader = self
+                    .deflate_reader
+                    .bit_reader()
+                    .borrow_reader_from_boundary();
+                match self.format {
+                    Format::Gzip => {
+                        let offset = reader.count;
+                        let mut gzip_reader = GzipReader::new(reader);
+                        if let Some(max_len) = self.limits.max_header_field {
+                            gzip_reader.set_max_field_len(max_len);
+                        }
+                        let header = match gzip_reader.get_header() {
+                            Some(header) => header?,
+                            None => return Ok(()),
+                        };
+                        if let Some(max_members) = self.limits.max_members {
+                            if self.member_index >= max_members {
+                                bail!(ErrorKind::LimitExceeded(Limit::Members(max_members)));
+                            }
+                        }
+                        let header = gzip_reader.parse_header(&header)?;
+                        debug!("read header");
+                        self.output_base += self.writer.byte_count() as u64;
+                        if self.members.is_some() {
+                            self.member = Some((offset, header));
+                        }
+                        self.writer.flush()?;
+                    }
+                    Format::Zlib => {
+                        let header = ZlibHeader::read(reader)?;
+                        if let Some(dictionary_id) = header.dictionary_id {
+                            match &self.dictionary {
+                                None => bail!(ErrorKind::DictionaryRequired),
+                                Some(dictionary) if adler32(dictionary) != dictionary_id => {
+                                    bail!(ErrorKind::DictionaryMismatch {
+                                        expected: dictionary_id,
+                                        actual: adler32(dictionary),
+                                    })
+                                }
+                                Some(dictionary) => self.writer.preload_history(dictionary),
+                            }
+                        }
+                    }
+                    Format::Raw => {
+                        if let Some(dictionary) = &self.dictionary {
+                            self.writer.preload_history(dictionary);
+                        }
+                    }
+                }
+                self.state = State::Block;
+            }
+            State::Block => {
+                self.record_checkpoint();
+                let mut info = BlockInfo {
+                    member: self.member_index,
+                    bit_offset: self.bit_position(),
+                    uncompressed_offset: self.output_position(),
+                    is_final: false,
+                    compression_type: CompressionType::Reserved,
+                    litlen_lengths: Vec::new(),
+                    distance_lengths: Vec::new(),
+                    stored_len: None,
+                };
+                let (block_header, reader) = match self.deflate_reader.next_block() {
+                    Some(block) => block?,
+                    None => bail!(ErrorKind::InvalidBlockType),
+                };
+                self.is_final_block = block_header.is_final;
+                (info.is_final, info.compression_type) =
+                    (block_header.is_final, block_header.compression_type);
+                self.state = match block_header.compression_type {
+                    CompressionType::Uncompressed => {
+                        debug!("uncompressed");
+                        let reader = reader.borrow_reader_from_boundary();
+                        let (len, nlen) = (
+                            reader.read_u16::<LittleEndian>()?,
+                            reader.read_u16::<LittleEndian>()?,
+                        );
+                        if len != !nlen {
+                            bail!(ErrorKind::StoredLengthMismatch)
+                        }
+                        info.stored_len = Some(len);
+                        State::Stored(len as usize)
+                    }
+                    CompressionType::FixedTree => {
+                        debug!("fixed");
+                        State::Compressed(Box::new(self.block_decoder.fixed_trees()?))
+                    }
+                    CompressionType::DynamicTree => {
+                        debug!("dynamic");
+                        let (litlen_lengths, distance_lengths) =
+                            read_code_lengths(reader, self.block_decoder.distance_codes())?;
+                        let trees = self
+                            .block_decoder
+                            .trees(&litlen_lengths, &distance_lengths)?;
+                        if self.blocks.is_some() {
+                            (info.litlen_lengths, info.distance_lengths) =
+                                (litlen_lengths, distance_lengths);
+                        }
+                        State::Compressed(Box::new(trees))
+                    }
+                    CompressionType::Reserved => bail!(ErrorKind::InvalidBlockType),
+                };
+                if let Some(blocks) = &mut self.blocks {
+                    blocks.push(BlockEvent::Block(info));
+                }
+            }
+            State::Stored(len) => {
+                let reader = self
+                    .deflate_reader
+                    .bit_reader()
+                    .borrow_reader_from_boundary();
+                let chunk_len = len.min(wanted).min(STORED_CHUNK_SIZE);
+                let mut data = vec![0_u8; chunk_len];
+                reader.read_exact(&mut data)?;
+                self.writer.write_all(&data)?;
+                self.state = match len - chunk_len {
+                    0 => {
+                        self.record_end_of_block();
+                        self.end_of_block()
+                    }
+                    rest => State::Stored(rest),
+                };
+            }
+            State::Compressed(trees) => {
+                let limit = self.writer.byte_count() + wanted;
+                let reader = self.deflate_reader.bit_reader();
+                let tokens = self.blocks.as_mut().filter(|_| self.record_tokens);
+                self.state = match decode_symbols(
+                    reader,
+                    &mut self.writer,
+                    &trees.0,
+                    &trees.1,
+                    limit,
+                    tokens,
+                )? {
+                    true => {
+                        self.record_end_of_block();
+                        self.end_of_block()
+                    }
+                    false => State::Compressed(trees),
+                };
+            }
+            State::Footer => {
+                let reader = self
+                    .deflate_reader
+                    .bit_reader()
+                    .borrow_reader_from_boundary();
+                match self.format {
+                    Format::Gzip if self.is_resumed => {
+                        MemberFooter::read(reader)?;
+                        self.is_resumed = false;
+                        self.state = State::Header;
+                    }
+                    Format::Gzip => {
+                        let footer = MemberFooter::read(reader)?;
+                        if self.writer.byte_count() as u32 != footer.data_size {
+                            bail!(ErrorKind::LengthMismatch {
+                                expected: footer.data_size,
+                                actual: self.writer.byte_count() as u32,
+                            });
+                        }
+                        let crc32 = self.writer.checksum();
+                        if footer.data_crc32 != crc32 {
+                            bail!(ErrorKind::CrcMismatch {
+                                expected: footer.data_crc32,
+                                actual: crc32,
+                            });
+                        }
+                        if let (Some(members), Some((offset, header))) =
+                            (&mut self.members, self.member.take())
+                        {
+                            members.push(MemberInfo {
+                                header,
+                                footer,
+                                offset,
+                                compressed_size: reader.count - offset,
+                                uncompressed_size: self.writer.byte_count() as u64,
+                            });
+                        }
+                        self.member_index += 1;
+                        self.state = State::Header;
+                    }
+                    Format::Zlib => {
+                        let (expected, actual) =
+                            (zlib::read_trailer(reader)?, self.writer.checksum());
+                        if expected != actual && !self.is_resumed {
+                            bail!(ErrorKind::AdlerMismatch { expected, actual });
+                        }
+                    }
+                    Format::Raw => (),
+                }
+            }
+            State::Done => (),
+        }
+        Ok(())
+    }
+
+    /// Fail if the decoded data exceeds the limits, keeping the output up to
+    /// `max_output` readable.
+    fn check_limits(&mut self) -> Result<()> {
+        let position = self.output_position();
+        if let Some(max_output) = self.limits.max_output {
+            if position > max_output {
+                let buffered = self.writer.inner_mut();
+                let excess = (position - max_output).min(buffered.len() as u64);
+                buffered.truncate(buffered.len() - excess as usize);
+                bail!(ErrorKind::LimitExceeded(Limit::OutputSize(max_output)));
+            }
+        }
+        if let Some(max_ratio) = self.limits.max_ratio {
+            let input_size = self.deflate_reader.bit_reader().get_ref().count.max(1);
+            if position > MIN_RATIO_CHECK_OUTPUT && position / input_size > max_ratio {
+                bail!(ErrorKind::LimitExceeded(Limit::Ratio(max_ratio)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Attach the current position to an error returned through `Read`, or
+    /// found while writing the decoded data.
+    pub fn locate(&mut self, err: io::Error) -> Error {
+        let offset = self.deflate_reader.bit_reader().get_ref().count;
+        Error::from_io(err, self.member_index, offset)
+    }
+
+    /// Run one decoding step, producing up to about `wanted` bytes.
+    pub(crate) fn advance(&mut self, wanted: usize) -> io::Result<()> {
+        if let Err(err) = self.step(wanted) {
+            self.state = State::Done;
+            let offset = self.deflate_reader.bit_reader().get_ref().count;
+            return Err(Error::from_anyhow(err, self.member_index, offset).into());
+        }
+        if let Err(err) = self.check_limits() {
+            self.state = State::Done;
+            let offset = self.deflate_reader.bit_reader().get_ref().count;
+            self.limit_error = Some(Error::from_anyhow(err, self.member_index, offset));
+        }
+        if let Some(mut callback) = self.progress.take() {
+            callback(&self.progress());
+            self.progress = Some(callback);
+        }
+        Ok(())
+    }
+
+    /// Whether `read_output` would return data or the end of the output.
+    pub(crate) fn has_output(&mut self) -> bool {
+        !self.writer.inner_mut().is_empty() || matches!(self.state, State::Done)
+    }
+
+    /// Read the data decoded by the previous steps.
+    pub(crate) fn read_output(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.writer.inner_mut().is_empty() {
+            if let Some(err) = self.limit_error.take() {
+                return Err(err.into());
+            }
+        }
+        self.writer.inner_mut().read(buf)
+    }
+
+    /// How many bytes the next step may produce without running out of the
+    /// input buffered by `R`, or None if it needs more input. Lets the caller
+    /// make sure that a step never stops halfway for lack of input.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn step_size(&mut self, wanted: usize) -> Option<usize> {
+        let reader = &mut self.deflate_reader.bit_reader().get_mut().inner;
+        let buffered = match reader.fill_buf() {
+            Ok(buffered) => buffered,
+            Err(_) => return Some(wanted),
+        };
+        let needed = match (&self.state, self.format) {
+            (State::Header, Format::Gzip) => {
+                let max_len = self
+                    .limits
+                    .max_header_field
+                    .map_or(usize::MAX, |len| 3 * len + 16);
+                match gzip::header_len(buffered) {
+                    Some(_) => 0,
+                    None if buffered.len() >= max_len => 0,
+                    None => buffered.len() + 1,
+                }
+            }
+            (State::Header, Format::Zlib) => match buffered.get(1) {
+                Some(flags) if (flags >> zlib::FDICT_OFFSET) & 1 != 0 => 6,
+                _ => 2,
+            },
+            (State::Block, _) => MAX_BLOCK_HEADER_LEN,
+            (State::Stored(0), _) => 0,
+            (State::Stored(_), _) => {
+                return (!buffered.is_empty()).then(|| wanted.min(buffered.len()))
+            }
+            (State::Compressed(_), _) => {
+                let size = buffered.len().saturating_sub(MAX_SYMBOL_LEN) / MAX_INPUT_PER_BYTE;
+                return (size > 0).then(|| wanted.min(size));
+            }
+            (State::Footer, Format::Gzip) => 8,
+            (State::Footer, Format::Zlib) => 4,
+            _ => 0,
+        };
+        (buffered.len() >= needed).then_some(wanted)
+    }
+
+    fn end_of_block(&self) -> State {
+        match self.is_final_block {
+            true => State::Footer,
+            false => State::Block,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Nothing can be decoded with a limit of 0.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while !self.has_output() {
+            self.advance(buf.len())?;
+        }
+        self.read_output(buf)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A `Decoder` of gzip members.
+pub struct GzipDecoder<R>(Decoder<R>);
+
+impl<R: BufRead> GzipDecoder<R> {
+    pub fn new(input: R) -> Self {
+        Self(Decoder::new(input, Format::Gzip))
+    }
+
+    pub fn into_decoder(self) -> Decoder<R> {
+        self.0
+    }
+}
+
+impl<R: BufRead> Read for GzipDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bit_reader::BitSequence;
+    use crate::bit_writer::BitWriter;
+    use crate::checksum::{Checksum, Crc32};
+    use crate::deflate::Deflate64;
+    use crate::gzip::CompressionMethod;
+    use crate::huffman_coding::{HuffmanEncoder, FIXED_DISTANCE_LENGTHS, FIXED_LITLEN_LENGTHS};
+    use crate::lz77::{Level, Lz77Encoder, Token};
+    use crate::test_util::{sample_data, XorShift};
+    use crate::{compress, compress_with_format, BLOCK_SIZE};
+    use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
+    #[test]
+    fn read_in_small_chunks() -> Result<()> {
+        let data = sample_data(100000);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compress(&data[..1000], &mut compressed, 1)?;
+
+        let mut decoder = GzipDecoder::new(io::BufReader::with_capacity(7, compressed.as_slice()));
+        let mut decompressed = Vec::new();
+        let mut buf = [0_u8; 13];
+        loop {
+            match decoder.read(&mut buf)? {
+                0 => break,
+                len => decompressed.extend_from_slice(&buf[..len]),
+            }
+        }
+        assert_eq!(decompressed[..data.len()], data);
+        assert_eq!(decompressed[data.len()..], data[..1000]);
+        Ok(())
+    }
+
+    #[test]
+    fn read_empty_buffer() -> Result<()> {
+        let mut compressed = Vec::new();
+        compress(&b"hello, world"[..], &mut compressed, 6)?;
+
+        let mut decoder = GzipDecoder::new(compressed.as_slice());
+        assert_eq!(decoder.read(&mut [])?, 0);
+        let mut buf = [0_u8; 5];
+        assert_eq!(decoder.read(&mut buf)?, 5);
+        assert_eq!(decoder.read(&mut [])?, 0);
+        let mut rest = Vec::new();
+        decoder.read_to_end(&mut rest)?;
+        assert_eq!(rest, b", world");
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_crc() -> Result<()> {
+        let mut compressed = Vec::new();
+        compress(&b"hello, world"[..], &mut compressed, 6)?;
+        let crc_pos = compressed.len() - 8;
+        compressed[crc_pos] ^= 1;
+
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = decoder.locate(err);
+        assert!(matches!(err.kind(), ErrorKind::CrcMismatch { .. }));
+        assert_eq!(err.member(), 0);
+        assert_eq!(err.offset(), compressed.len() as u64);
+
+        let mut decoder = Decoder::new(&compressed[..crc_pos], Format::Gzip);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            decoder.locate(err).kind(),
+            ErrorKind::TruncatedInput
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn member_info() -> Result<()> {
+        let mut compressed = Vec::new();
+        compress(&b"first member"[..], &mut compressed, 6)?;
+        let first_len = compressed.len() as u64;
+        let header = MemberHeader {
+            compression_method: CompressionMethod::Deflate,
+            modification_time: 1234567890,
+            extra: None,
+            name: Some("second.txt".to_string()),
+            comment: Some("a comment".to_string()),
+            extra_flags: 0,
+            os: 3,
+            has_crc: true,
+            is_text: false,
+        };
+        header.write(&mut compressed)?;
+        let mut raw = Vec::new();
+        compress_with_format(&b"second"[..], &mut raw, 6, Format::Raw)?;
+        compressed.extend_from_slice(&raw);
+        let mut checksum = Crc32::new();
+        checksum.update(b"second");
+        MemberFooter {
+            data_crc32: checksum.finish(),
+            data_size: 6,
+        }
+        .write(&mut compressed)?;
+
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+        decoder.record_members();
+        decoder.read_to_end(&mut Vec::new())?;
+        let members = decoder.take_members();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].offset, 0);
+        assert_eq!(members[0].compressed_size, first_len);
+        assert_eq!(members[0].uncompressed_size, 12);
+        assert_eq!(members[0].header.name, None);
+        assert_eq!(members[1].offset, first_len);
+        assert_eq!(
+            members[1].compressed_size,
+            compressed.len() as u64 - first_len
+        );
+        assert_eq!(members[1].uncompressed_size, 6);
+        assert_eq!(members[1].header.name.as_deref(), Some("second.txt"));
+        assert_eq!(members[1].header.comment.as_deref(), Some("a comment"));
+        assert_eq!(members[1].header.modification_time, 1234567890);
+        assert_eq!(members[1].header.os_name(), "Unix");
+        assert!(decoder.take_members().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn progress() -> Result<()> {
+        let data = sample_data(300000);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compress(&data[..1000], &mut compressed, 1)?;
+
+        let updates = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+        decoder.set_progress({
+            let updates = updates.clone();
+            move |progress| updates.lock().unwrap().push(*progress)
+        });
+        decoder.read_to_end(&mut Vec::new())?;
+        let updates = updates.lock().unwrap();
+        assert!(updates.len() > 2);
+        assert!(updates
+            .windows(2)
+            .all(|pair| pair[0].compressed <= pair[1].compressed
+                && pair[0].uncompressed <= pair[1].uncompressed
+                && pair[0].member <= pair[1].member));
+        assert!(updates.iter().any(|progress| progress.member == 1));
+        let last = Progress {
+            compressed: compressed.len() as u64,
+            uncompressed: data.len() as u64 + 1000,
+            member: 2,
+        };
+        assert_eq!(updates.last(), Some(&last));
+        assert_eq!(decoder.progress(), last);
+        Ok(())
+    }
+
+    #[test]
+    fn deflate64() -> Result<()> {
+        // A fixed block with "abc", a match of 65538 bytes at distance 3 (length
+        // symbol 285 with 16 extra bits) and one of 10 bytes at distance 40000
+        // (distance symbol 30 with 14 extra bits).
+        let litlen = HuffmanEncoder::from_lengths(&FIXED_LITLEN_LENGTHS);
+        let distance = HuffmanEncoder::from_lengths(&FIXED_DISTANCE_LENGTHS);
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(0b011, 3))?;
+        for &byte in b"abc" {
+            litlen.write_symbol(&mut writer, byte as u16)?;
+        }
+        litlen.write_symbol(&mut writer, 285)?;
+        writer.write_bits(BitSequence::new(65535, 16))?;
+        distance.write_symbol(&mut writer, 2)?;
+        litlen.write_symbol(&mut writer, 264)?;
+        distance.write_symbol(&mut writer, 30)?;
+        writer.write_bits(BitSequence::new(40000 - 32769, 14))?;
+        litlen.write_symbol(&mut writer, 256)?;
+        let compressed = writer.finish()?;
+
+        let mut expected: Vec<u8> = b"abc".iter().copied().cycle().take(65541).collect();
+        expected.extend_from_within(expected.len() - 40000..expected.len() - 39990);
+        let mut decoder =
+            Decoder::with_block_decoder(compressed.as_slice(), Format::Raw, Box::new(Deflate64));
+        decoder.record_blocks(true);
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed)?;
+        assert!(decompressed == expected);
+        let matches: Vec<_> = decoder
+            .take_blocks()
+            .into_iter()
+            .filter_map(|event| match event {
+                BlockEvent::Match { length, distance } => Some((length, distance)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(matches, [(65538, 3), (10, 40000)]);
+
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Raw);
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn record_blocks() -> Result<()> {
+        let data = sample_data(300000);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+        decoder.record_blocks(true);
+        let mut decompressed = Vec::new();
+        let mut events = Vec::new();
+        let mut buf = [0_u8; 4096];
+        while decoder.read(&mut buf)? > 0 {
+            events.extend(decoder.take_blocks());
+        }
+        events.extend(decoder.take_blocks());
+
+        let mut block_end = 80;
+        let mut blocks = Vec::new();
+        for event in events {
+            match event {
+                BlockEvent::Block(block) => {
+                    assert_eq!(block.bit_offset, block_end);
+                    assert_eq!(block.uncompressed_offset, decompressed.len() as u64);
+                    blocks.push(block);
+                }
+                BlockEvent::Literal(value) => decompressed.push(value),
+                BlockEvent::Match { length, distance } => {
+                    for _ in 0..length {
+                        decompressed.push(decompressed[decompressed.len() - distance as usize]);
+                    }
+                }
+                BlockEvent::EndOfBlock { bit_offset } => block_end = bit_offset,
+            }
+        }
+        assert_eq!(decompressed, data);
+        assert_eq!(block_end.div_ceil(8), compressed.len() as u64 - 8);
+        assert!(blocks.len() > 1);
+        assert!(blocks.last().unwrap().is_final);
+        assert!(blocks.iter().all(|block| match block.compression_type {
+            CompressionType::DynamicTree => block.litlen_lengths.len() >= 257,
+            _ => block.litlen_lengths.is_empty(),
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> Result<()> {
+        let data = vec![0_u8; 3 << 20];
+        let mut compressed = Vec::new();
+        for _ in 0..3 {
+            compress(data.as_slice(), &mut compressed, 6)?;
+        }
+        let decode = |limits: Limits| {
+            let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+            decoder.set_limits(limits);
+            let mut output = Vec::new();
+            let err = decoder.read_to_end(&mut output).unwrap_err();
+            (decoder.locate(err), output.len())
+        };
+
+        let (err, len) = decode(Limits {
+            max_output: Some(5 << 20),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::OutputSize(_))
+        ));
+        assert_eq!(len, 5 << 20);
+        let (err, _) = decode(Limits {
+            max_ratio: Some(100),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::Ratio(100))
+        ));
+        let (err, len) = decode(Limits {
+            max_members: Some(2),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::Members(2))
+        ));
+        assert_eq!((err.member(), len), (2, data.len() * 2));
+
+        let header = MemberHeader {
+            name: Some("n".repeat(100)),
+            ..MemberHeader::with_level(6)
+        };
+        let mut compressed = Vec::new();
+        header.write(&mut compressed)?;
+        let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+        decoder.set_limits(Limits {
+            max_header_field: Some(99),
+            ..Limits::default()
+        });
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            decoder.locate(err).kind(),
+            ErrorKind::LimitExceeded(Limit::HeaderField(99))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_and_raw() -> Result<()> {
+        let data = b"zlib and raw deflate, zlib and raw deflate".repeat(100);
+        for format in [Format::Zlib, Format::Raw] {
+            let mut compressed = Vec::new();
+            compress_with_format(data.as_slice(), &mut compressed, 6, format)?;
+            let mut decompressed = Vec::new();
+            Decoder::new(compressed.as_slice(), format).read_to_end(&mut decompressed)?;
+            assert_eq!(decompressed, data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn zlib_preset_dictionary() -> Result<()> {
+        // Produced by zlib with the dictionary "hello world" for the input "hello world!".
+        let compressed = [
+            0x78, 0xbb, 0x1a, 0x0b, 0x04, 0x5d, 0xcb, 0x40, 0x30, 0x15, 0x01, 0x1e, 0x89, 0x04,
+            0x7e,
+        ];
+        let mut decoder = Decoder::new(&compressed[..], Format::Zlib);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            decoder.locate(err).kind(),
+            ErrorKind::DictionaryRequired
+        ));
+
+        let mut decoder = Decoder::new(&compressed[..], Format::Zlib);
+        decoder.set_dictionary(b"hello world");
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed)?;
+        assert_eq!(decompressed, b"hello world!");
+        Ok(())
+    }
+
+    static CRC_CHECKER: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    const HISTORY_SIZE: usize = 32768;
+
+    /// The `TrackingWriter` before the ring buffer, which keeps the history in
+    /// a `VecDeque`. Kept as the baseline of the benchmark.
+    struct DequeWriter {
+        output: Vec<u8>,
+        hist: VecDeque<u8>,
+        digest: Digest<'static, u32>,
+    }
+
+    impl DequeWriter {
+        fn new(output: Vec<u8>) -> Self {
+            Self {
+                output,
+                hist: VecDeque::with_capacity(HISTORY_SIZE),
+                digest: CRC_CHECKER.digest(),
+            }
+        }
+
+        fn write(&mut self, buf: &[u8]) {
+            self.output.extend_from_slice(buf);
+            self.digest.update(buf);
+            let kept = buf.len().min(HISTORY_SIZE);
+            let extra = (self.hist.len() + kept).saturating_sub(HISTORY_SIZE);
+            self.hist.drain(..extra);
+            self.hist.extend(&buf[buf.len() - kept..]);
+        }
+
+        fn write_previous(&mut self, dist: usize, len: usize) {
+            let begin = self.hist.len() - dist;
+            self.hist.make_contiguous();
+            let data = self.hist.as_slices().0[begin..].to_vec();
+            let mut written = 0;
+            while written < len {
+                let chunk = dist.min(len - written);
+                self.write(&data[..chunk]);
+                written += chunk;
+            }
+        }
+    }
+
+    // Decompresses the files of the directory in RIPGZIP_CORPUS (e.g. the Canterbury
+    // or Silesia corpus), or generated data if it is not set, and compares the
+    // window of `TrackingWriter` with `DequeWriter`.
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_decompress() -> Result<()> {
+        let mut corpus = Vec::new();
+        match std::env::var_os("RIPGZIP_CORPUS") {
+            Some(dir) => {
+                for entry in std::fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    if path.is_file() {
+                        corpus.push((path.display().to_string(), std::fs::read(&path)?));
+                    }
+                }
+                corpus.sort();
+            }
+            None => {
+                let words = ["decoder ", "window ", "ring ", "buffer ", "huffman ", "\n"];
+                let text = (0..1_u64 << 21)
+                    .map(|i| words[(i * i % 7919 % 6) as usize])
+                    .collect::<String>();
+                let binary = XorShift::new(1)
+                    .take(1 << 23)
+                    .map(|state| (state % 16) as u8)
+                    .collect();
+                corpus.push(("text".to_string(), text.into_bytes()));
+                corpus.push(("binary".to_string(), binary));
+                corpus.push(("zeros".to_string(), vec![0; 1 << 24]));
+            }
+        }
+
+        for (name, data) in &corpus {
+            let mut compressed = Vec::new();
+            compress(data.as_slice(), &mut compressed, 6)?;
+            let start = std::time::Instant::now();
+            let mut decompressed = Vec::with_capacity(data.len());
+            Decoder::new(compressed.as_slice(), Format::Gzip).read_to_end(&mut decompressed)?;
+            let decoder_time = start.elapsed();
+            assert_eq!(&decompressed, data);
+
+            // The windows alone, replaying the matches found by the encoder.
+            let mut encoder = Lz77Encoder::new(Level::new(6));
+            let mut tokens = Vec::new();
+            for block in data.chunks(BLOCK_SIZE) {
+                encoder.encode(block, &mut tokens);
+            }
+
+            let start = std::time::Instant::now();
+            let mut writer = TrackingWriter::new(Vec::with_capacity(data.len()));
+            for token in &tokens {
+                match *token {
+                    Token::Literal(byte) => writer.write_all(&[byte])?,
+                    Token::Match { len, dist } => {
+                        writer.write_previous(dist as usize, len as usize)?
+                    }
+                }
+            }
+            let crc = writer.checksum();
+            let ring_buffer_time = start.elapsed();
+            assert_eq!(writer.inner_mut(), data);
+
+            let start = std::time::Instant::now();
+            let mut writer = DequeWriter::new(Vec::with_capacity(data.len()));
+            for token in &tokens {
+                match *token {
+                    Token::Literal(byte) => writer.write(&[byte]),
+                    Token::Match { len, dist } => {
+                        writer.write_previous(dist as usize, len as usize)
+                    }
+                }
+            }
+            assert_eq!(writer.digest.finalize(), crc);
+            let deque_time = start.elapsed();
+            assert_eq!(&writer.output, data);
+
+            let megabytes = data.len() as f64 / (1 << 20) as f64;
+            println!(
+                "{}: decoder {:.1} MB/s, ring buffer {:.1} MB/s, deque {:.1} MB/s",
+                name,
+                megabytes / decoder_time.as_secs_f64(),
+                megabytes / ring_buffer_time.as_secs_f64(),
+                megabytes / deque_time.as_secs_f64(),
+            );
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,15 @@
 #![forbid(unsafe_code)]
 
-use std::io::BufRead;
+use std::io::{BufRead, Write};
 
-use crate::bit_reader::BitReader;
+use crate::bit_reader::{BitReader, BitSequence};
+use crate::bit_writer::BitWriter;
+use crate::huffman_coding::{
+    encode_distance, encode_length, lengths_from_frequencies, DistanceToken, HuffmanCodeWord,
+    HuffmanCoding, HuffmanEncoder, LitLenToken, FIXED_DISTANCE_LENGTHS, FIXED_LITLEN_LENGTHS,
+};
+use crate::lz77::Token;
+use crate::tracking_writer::HISTORY_SIZE;
 use anyhow::{anyhow, Result};
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -13,7 +20,7 @@ pub struct BlockHeader {
     pub compression_type: CompressionType,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CompressionType {
     Uncompressed = 0,
     FixedTree = 1,
@@ -32,6 +39,14 @@ impl<T: BufRead> DeflateReader<T> {
         Self { bit_reader }
     }
 
+    pub fn into_inner(self) -> T {
+        self.bit_reader.into_inner()
+    }
+
+    pub fn bit_reader(&mut self) -> &mut BitReader<T> {
+        &mut self.bit_reader
+    }
+
     pub fn next_block(&mut self) -> Option<Result<(BlockHeader, &mut BitReader<T>)>> {
         let mut result = BlockHeader {
             is_final: false,
@@ -69,3 +84,373 @@ impl<T: BufRead> DeflateReader<T> {
         Some(Ok((result, &mut self.bit_reader)))
     }
 }
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A variant of the deflate format: the meaning of the litlen and distance
+/// symbols of the compressed blocks, and how far back matches may refer.
+pub trait BlockDecoder: Send + Sync {
+    /// Largest distance of a match.
+    fn history_size(&self) -> usize;
+
+    /// Number of distance codes a dynamic block may define.
+    fn distance_codes(&self) -> usize;
+
+    fn litlen_token(&self, symbol: u16) -> Option<LitLenToken>;
+
+    fn distance_token(&self, symbol: u16) -> Option<DistanceToken>;
+
+    fn trees(
+        &self,
+        litlen_lengths: &[u8],
+        distance_lengths: &[u8],
+    ) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+        let litlen_coder =
+            HuffmanCoding::from_lengths_with(litlen_lengths, |symbol| self.litlen_token(symbol))?;
+        let distance_coder = HuffmanCoding::from_lengths_with(distance_lengths, |symbol| {
+            self.distance_token(symbol)
+        })?;
+        Ok((litlen_coder, distance_coder))
+    }
+
+    fn fixed_trees(&self) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+        self.trees(&FIXED_LITLEN_LENGTHS, &FIXED_DISTANCE_LENGTHS)
+    }
+}
+
+/// Deflate as described in RFC 1951.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deflate;
+
+impl BlockDecoder for Deflate {
+    fn history_size(&self) -> usize {
+        HISTORY_SIZE
+    }
+
+    fn distance_codes(&self) -> usize {
+        30
+    }
+
+    fn litlen_token(&self, symbol: u16) -> Option<LitLenToken> {
+        LitLenToken::try_from(HuffmanCodeWord(symbol)).ok()
+    }
+
+    fn distance_token(&self, symbol: u16) -> Option<DistanceToken> {
+        DistanceToken::try_from(HuffmanCodeWord(symbol)).ok()
+    }
+}
+
+/// Deflate64, the "enhanced deflate" of zip (method 9): a 64 KiB history,
+/// length symbol 285 with 16 extra bits and distance symbols 30 and 31.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Deflate64;
+
+impl BlockDecoder for Deflate64 {
+    fn history_size(&self) -> usize {
+        2 * HISTORY_SIZE
+    }
+
+    fn distance_codes(&self) -> usize {
+        32
+    }
+
+    fn litlen_token(&self, symbol: u16) -> Option<LitLenToken> {
+        match symbol {
+            285 => Some(LitLenToken::Length {
+                base: 3,
+                extra_bits: 16,
+            }),
+            _ => Deflate.litlen_token(symbol),
+        }
+    }
+
+    fn distance_token(&self, symbol: u16) -> Option<DistanceToken> {
+        match symbol {
+            30 | 31 => Some(DistanceToken {
+                base: 32769 + (symbol - 30) * 16384,
+                extra_bits: 14,
+            }),
+            _ => Deflate.distance_token(symbol),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAX_STORED_LEN: usize = 65535;
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Clone, Copy)]
+enum EncodedToken {
+    Literal(u16),
+    Match {
+        len_symbol: u16,
+        len_extra: BitSequence,
+        dist_symbol: u16,
+        dist_extra: BitSequence,
+    },
+}
+
+struct DynamicTrees {
+    litlen: HuffmanEncoder,
+    distance: HuffmanEncoder,
+    code_length: HuffmanEncoder,
+    code_length_count: usize,
+    hlit: usize,
+    hdist: usize,
+    // Code length symbols with their extra bits, see RFC 1951, section 3.2.7.
+    code_length_symbols: Vec<(u16, BitSequence)>,
+}
+
+pub struct DeflateWriter<T> {
+    bit_writer: BitWriter<T>,
+    fixed_litlen: HuffmanEncoder,
+    fixed_distance: HuffmanEncoder,
+}
+
+impl<T: Write> DeflateWriter<T> {
+    pub fn new(bit_writer: BitWriter<T>) -> Self {
+        Self {
+            bit_writer,
+            fixed_litlen: HuffmanEncoder::from_lengths(&FIXED_LITLEN_LENGTHS),
+            fixed_distance: HuffmanEncoder::from_lengths(&FIXED_DISTANCE_LENGTHS),
+        }
+    }
+
+    /// Write `tokens` as one block, choosing the cheapest block type.
+    /// `data` is the uncompressed content of the block.
+    pub fn write_block(&mut self, tokens: &[Token], data: &[u8], is_final: bool) -> Result<()> {
+        let encoded: Vec<EncodedToken> = tokens.iter().map(|&token| encode_token(token)).collect();
+        let trees = build_dynamic_trees(&encoded);
+
+        let dynamic_cost =
+            dynamic_header_cost(&trees) + tokens_cost(&encoded, &trees.litlen, &trees.distance);
+        let fixed_cost = tokens_cost(&encoded, &self.fixed_litlen, &self.fixed_distance);
+        let stored_cost =
+            8 * data.len() + 40 * std::cmp::max(1, data.len().div_ceil(MAX_STORED_LEN));
+
+        if stored_cost <= dynamic_cost && stored_cost <= fixed_cost {
+            self.write_stored(data, is_final)
+        } else if fixed_cost <= dynamic_cost {
+            self.write_header(is_final, CompressionType::FixedTree)?;
+            let (litlen, distance) = (&self.fixed_litlen, &self.fixed_distance);
+            write_tokens(&mut self.bit_writer, &encoded, litlen, distance)
+        } else {
+            self.write_header(is_final, CompressionType::DynamicTree)?;
+            self.write_dynamic_trees(&trees)?;
+            write_tokens(
+                &mut self.bit_writer,
+                &encoded,
+                &trees.litlen,
+                &trees.distance,
+            )
+        }
+    }
+
+    pub fn finish(self) -> Result<T> {
+        Ok(self.bit_writer.finish()?)
+    }
+
+    fn write_header(&mut self, is_final: bool, compression_type: CompressionType) -> Result<()> {
+        self.bit_writer
+            .write_bits(BitSequence::new(is_final as u16, 1))?;
+        self.bit_writer
+            .write_bits(BitSequence::new(compression_type as u16, 2))?;
+        Ok(())
+    }
+
+    fn write_stored(&mut self, data: &[u8], is_final: bool) -> Result<()> {
+        let mut chunks = data.chunks(MAX_STORED_LEN).peekable();
+        if chunks.peek().is_none() {
+            self.write_header(is_final, CompressionType::Uncompressed)?;
+            self.bit_writer
+                .borrow_writer_from_boundary()?
+                .write_all(&[0, 0, 0xff, 0xff])?;
+        }
+        while let Some(chunk) = chunks.next() {
+            let is_last = chunks.peek().is_none();
+            self.write_header(is_final && is_last, CompressionType::Uncompressed)?;
+            let len = chunk.len() as u16;
+            let writer = self.bit_writer.borrow_writer_from_boundary()?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(&(!len).to_le_bytes())?;
+            writer.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_dynamic_trees(&mut self, trees: &DynamicTrees) -> Result<()> {
+        let writer = &mut self.bit_writer;
+        writer.write_bits(BitSequence::new((trees.hlit - 257) as u16, 5))?;
+        writer.write_bits(BitSequence::new((trees.hdist - 1) as u16, 5))?;
+        writer.write_bits(BitSequence::new((trees.code_length_count - 4) as u16, 4))?;
+        for &symbol in &CODE_LENGTH_ORDER[..trees.code_length_count] {
+            let len = trees.code_length.code_len(symbol as u16);
+            writer.write_bits(BitSequence::new(len as u16, 3))?;
+        }
+        for &(symbol, extra) in &trees.code_length_symbols {
+            trees.code_length.write_symbol(writer, symbol)?;
+            writer.write_bits(extra)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_token(token: Token) -> EncodedToken {
+    match token {
+        Token::Literal(byte) => EncodedToken::Literal(byte as u16),
+        Token::Match { len, dist } => {
+            let (len_symbol, len_extra) = encode_length(len);
+            let (dist_symbol, dist_extra) = encode_distance(dist);
+            EncodedToken::Match {
+                len_symbol,
+                len_extra,
+                dist_symbol,
+                dist_extra,
+            }
+        }
+    }
+}
+
+fn build_dynamic_trees(encoded: &[EncodedToken]) -> DynamicTrees {
+    let mut litlen_freqs = [0_usize; 286];
+    let mut distance_freqs = [0_usize; 30];
+    litlen_freqs[256] = 1;
+    for token in encoded {
+        match *token {
+            EncodedToken::Literal(symbol) => litlen_freqs[symbol as usize] += 1,
+            EncodedToken::Match {
+                len_symbol,
+                dist_symbol,
+                ..
+            } => {
+                litlen_freqs[len_symbol as usize] += 1;
+                distance_freqs[dist_symbol as usize] += 1;
+            }
+        }
+    }
+    if distance_freqs.iter().all(|&freq| freq == 0) {
+        distance_freqs[0] = 1;
+    }
+
+    let litlen_lengths = lengths_from_frequencies(&litlen_freqs, 15);
+    let distance_lengths = lengths_from_frequencies(&distance_freqs, 15);
+    let hlit = std::cmp::max(257, last_used(&litlen_lengths));
+    let hdist = std::cmp::max(1, last_used(&distance_lengths));
+
+    let mut code_length_symbols = Vec::new();
+    run_length_encode(&litlen_lengths[..hlit], &mut code_length_symbols);
+    run_length_encode(&distance_lengths[..hdist], &mut code_length_symbols);
+    let mut code_length_freqs = [0_usize; 19];
+    for &(symbol, _) in &code_length_symbols {
+        code_length_freqs[symbol as usize] += 1;
+    }
+    let code_length_lengths = lengths_from_frequencies(&code_length_freqs, 7);
+    let code_length_count = std::cmp::max(
+        4,
+        last_used(&CODE_LENGTH_ORDER.map(|symbol| code_length_lengths[symbol])),
+    );
+
+    DynamicTrees {
+        litlen: HuffmanEncoder::from_lengths(&litlen_lengths),
+        distance: HuffmanEncoder::from_lengths(&distance_lengths),
+        code_length: HuffmanEncoder::from_lengths(&code_length_lengths),
+        code_length_count,
+        hlit,
+        hdist,
+        code_length_symbols,
+    }
+}
+
+fn last_used(lengths: &[u8]) -> usize {
+    lengths
+        .iter()
+        .rposition(|&len| len != 0)
+        .map_or(0, |ind| ind + 1)
+}
+
+fn run_length_encode(lengths: &[u8], symbols: &mut Vec<(u16, BitSequence)>) {
+    let mut ind = 0;
+    while ind < lengths.len() {
+        let len = lengths[ind];
+        let run = lengths[ind..].iter().take_while(|&&x| x == len).count();
+        if len == 0 && run >= 11 {
+            let run = std::cmp::min(run, 138);
+            symbols.push((18, BitSequence::new(run as u16 - 11, 7)));
+            ind += run;
+        } else if len == 0 && run >= 3 {
+            symbols.push((17, BitSequence::new(run as u16 - 3, 3)));
+            ind += run;
+        } else if len != 0 && run >= 4 {
+            symbols.push((len as u16, BitSequence::new(0, 0)));
+            let run = std::cmp::min(run - 1, 6);
+            symbols.push((16, BitSequence::new(run as u16 - 3, 2)));
+            ind += run + 1;
+        } else {
+            symbols.push((len as u16, BitSequence::new(0, 0)));
+            ind += 1;
+        }
+    }
+}
+
+fn dynamic_header_cost(trees: &DynamicTrees) -> usize {
+    let symbols_cost: usize = trees
+        .code_length_symbols
+        .iter()
+        .map(|&(symbol, extra)| (trees.code_length.code_len(symbol) + extra.len()) as usize)
+        .sum();
+    14 + 3 * trees.code_length_count + symbols_cost
+}
+
+fn tokens_cost(
+    encoded: &[EncodedToken],
+    litlen: &HuffmanEncoder,
+    distance: &HuffmanEncoder,
+) -> usize {
+    let tokens_cost: usize = encoded
+        .iter()
+        .map(|token| match *token {
+            EncodedToken::Literal(symbol) => litlen.code_len(symbol) as usize,
+            EncodedToken::Match {
+                len_symbol,
+                len_extra,
+                dist_symbol,
+                dist_extra,
+            } => {
+                (litlen.code_len(len_symbol)
+                    + len_extra.len()
+                    + distance.code_len(dist_symbol)
+                    + dist_extra.len()) as usize
+            }
+        })
+        .sum();
+    3 + tokens_cost + litlen.code_len(256) as usize
+}
+
+fn write_tokens<T: Write>(
+    writer: &mut BitWriter<T>,
+    encoded: &[EncodedToken],
+    litlen: &HuffmanEncoder,
+    distance: &HuffmanEncoder,
+) -> Result<()> {
+    for token in encoded {
+        match *token {
+            EncodedToken::Literal(symbol) => litlen.write_symbol(writer, symbol)?,
+            EncodedToken::Match {
+                len_symbol,
+                len_extra,
+                dist_symbol,
+                dist_extra,
+            } => {
+                litlen.write_symbol(writer, len_symbol)?;
+                writer.write_bits(len_extra)?;
+                distance.write_symbol(writer, dist_symbol)?;
+                writer.write_bits(dist_extra)?;
+            }
+        }
+    }
+    litlen.write_symbol(writer, 256)?;
+    Ok(())
+}
@@ -0,0 +1,235 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+use std::io;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// What went wrong while decoding.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The member does not start with the gzip magic bytes.
+    BadMagic,
+    UnsupportedMethod(u8),
+    /// A malformed header field other than the magic bytes and the method.
+    InvalidHeader(&'static str),
+    HeaderCrcMismatch {
+        expected: u16,
+        actual: u16,
+    },
+    /// The zlib stream needs a preset dictionary, but none was given.
+    DictionaryRequired,
+    DictionaryMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    InvalidBlockType,
+    /// LEN and NLEN of a stored block do not match.
+    StoredLengthMismatch,
+    /// The code lengths of a dynamic block do not describe valid Huffman codes.
+    InvalidCodeLengths(&'static str),
+    /// A bit sequence that is not a code of the current Huffman tree.
+    InvalidCode,
+    DistanceTooFar {
+        distance: usize,
+        available: usize,
+    },
+    /// ISIZE does not match the decompressed size (modulo 2^32).
+    LengthMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    CrcMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    AdlerMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    TruncatedInput,
+    LimitExceeded(Limit),
+    Io(io::Error),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not in gzip format"),
+            Self::UnsupportedMethod(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            Self::InvalidHeader(what) => write!(f, "invalid header: {}", what),
+            Self::HeaderCrcMismatch { expected, actual } => write!(
+                f,
+                "header crc16 check failed: expected {:04x}, got {:04x}",
+                expected, actual
+            ),
+            Self::DictionaryRequired => write!(f, "preset dictionary required"),
+            Self::DictionaryMismatch { expected, actual } => write!(
+                f,
+                "preset dictionary id mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            Self::InvalidBlockType => write!(f, "unsupported block type"),
+            Self::StoredLengthMismatch => write!(f, "nlen check failed"),
+            Self::InvalidCodeLengths(what) => write!(f, "invalid code lengths: {}", what),
+            Self::InvalidCode => write!(f, "invalid huffman code"),
+            Self::DistanceTooFar {
+                distance,
+                available,
+            } => write!(
+                f,
+                "distance {} is too far back, only {} bytes available",
+                distance, available
+            ),
+            Self::LengthMismatch { expected, actual } => write!(
+                f,
+                "length check failed: expected {}, got {}",
+                expected, actual
+            ),
+            Self::CrcMismatch { expected, actual } => write!(
+                f,
+                "crc32 check failed: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            Self::AdlerMismatch { expected, actual } => write!(
+                f,
+                "adler32 check failed: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+            Self::TruncatedInput => write!(f, "unexpected end of file"),
+            Self::LimitExceeded(limit) => write!(f, "limit exceeded: {}", limit),
+            Self::Io(_) => write!(f, "i/o error"),
+        }
+    }
+}
+
+impl std::error::Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ErrorKind {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => Self::TruncatedInput,
+            _ => Self::Io(err),
+        }
+    }
+}
+
+/// A decompression limit, see `Limits`, with its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    OutputSize(u64),
+    Ratio(u64),
+    Members(usize),
+    HeaderField(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutputSize(size) => write!(f, "more than {} bytes of output", size),
+            Self::Ratio(ratio) => write!(f, "compression ratio above {}", ratio),
+            Self::Members(count) => write!(f, "more than {} members", count),
+            Self::HeaderField(len) => write!(f, "header field longer than {} bytes", len),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decoding error with the position where it happened.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    member: usize,
+    offset: u64,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, member: usize, offset: u64) -> Self {
+        Self {
+            kind,
+            member,
+            offset,
+        }
+    }
+
+    /// Convert an error of the internal decoding routines, which are either an
+    /// `ErrorKind` or an `io::Error`.
+    pub(crate) fn from_anyhow(err: anyhow::Error, member: usize, offset: u64) -> Self {
+        let kind = match err.downcast::<ErrorKind>() {
+            Ok(kind) => kind,
+            Err(err) => match err.downcast::<io::Error>() {
+                Ok(err) => return Self::from_io(err, member, offset),
+                Err(err) => ErrorKind::Io(io::Error::other(err.to_string())),
+            },
+        };
+        Self::new(kind, member, offset)
+    }
+
+    /// Unwrap an `Error` passed through `io::Error`, or locate a plain I/O error.
+    pub(crate) fn from_io(err: io::Error, member: usize, offset: u64) -> Self {
+        match err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            true => *err.into_inner().unwrap().downcast::<Error>().unwrap(),
+            false => Self::new(err.into(), member, offset),
+        }
+    }
+
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    /// Index of the member (always 0 for zlib and raw deflate data).
+    pub fn member(&self) -> usize {
+        self.member
+    }
+
+    /// Number of compressed bytes consumed when the error was detected.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Shift the position of an error found in a part of the input that starts
+    /// at the given member and offset.
+    pub(crate) fn relocate(mut self, member: usize, offset: u64) -> Self {
+        self.member += member;
+        self.offset += offset;
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (member {}, offset {})",
+            self.kind, self.member, self.offset
+        )
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.kind.source()
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match &err.kind {
+            ErrorKind::TruncatedInput => io::ErrorKind::UnexpectedEof,
+            ErrorKind::Io(inner) => inner.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
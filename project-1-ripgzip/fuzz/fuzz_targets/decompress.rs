@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ripgzip::Format;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the format, so that one corpus covers all of them.
+    let (format, data) = match data.split_first() {
+        Some((&byte, data)) => match byte % 3 {
+            0 => (Format::Gzip, data),
+            1 => (Format::Zlib, data),
+            _ => (Format::Raw, data),
+        },
+        None => return,
+    };
+    let _ = ripgzip::decompress_with_format(data, std::io::sink(), format);
+});
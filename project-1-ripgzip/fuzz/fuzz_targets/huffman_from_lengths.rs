@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ripgzip::fuzzing::{BitReader, HuffmanCoding, LitLenToken};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte gives the number of code lengths, up to the 288 litlen
+    // codes, and the data after the lengths is decoded with the code.
+    let (&count, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let (lengths, data) = data.split_at((count as usize + 33).min(data.len()));
+    let coding = match HuffmanCoding::<LitLenToken>::from_lengths(lengths) {
+        Ok(coding) => coding,
+        Err(_) => return,
+    };
+    let mut reader = BitReader::new(data);
+    while coding.read_symbol(&mut reader).is_ok() {}
+});
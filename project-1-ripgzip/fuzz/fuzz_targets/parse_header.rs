@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ripgzip::fuzzing::GzipReader;
+
+fuzz_target!(|data: &[u8]| {
+    let (header, rest) = data.split_at(data.len().min(10));
+    let mut reader = GzipReader::new(rest);
+    reader.set_max_field_len(1024);
+    let _ = reader.parse_header(header);
+});
@@ -1,13 +1,15 @@
 #![forbid(unsafe_code)]
 
-use std::io::BufRead;
+use std::io::{BufRead, Read, Write};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use byteorder::{LittleEndian, ReadBytesExt};
 use crc::Crc;
 use log::debug;
 use std::str;
 
+use crate::error::{ErrorKind, Limit};
+
 ////////////////////////////////////////////////////////////////////////////////
 
 const ID1: u8 = 0x1f;
@@ -20,10 +22,14 @@ const FHCRC_OFFSET: u8 = 1;
 const FEXTRA_OFFSET: u8 = 2;
 const FNAME_OFFSET: u8 = 3;
 const FCOMMENT_OFFSET: u8 = 4;
+const RESERVED_FLAGS: u8 = 0xe0;
+
+const MAX_KNOWN_OS: u8 = 13;
+const OS_UNKNOWN: u8 = 255;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MemberHeader {
     pub compression_method: CompressionMethod,
     pub modification_time: u32,
@@ -37,6 +43,48 @@ pub struct MemberHeader {
 }
 
 impl MemberHeader {
+    /// Header without optional fields for data compressed with the given level.
+    pub fn with_level(level: u32) -> Self {
+        Self {
+            compression_method: CompressionMethod::Deflate,
+            modification_time: 0,
+            extra: None,
+            name: None,
+            comment: None,
+            extra_flags: match level {
+                1 => 4,
+                9 => 2,
+                _ => 0,
+            },
+            os: OS_UNKNOWN,
+            has_crc: false,
+            is_text: false,
+        }
+    }
+
+    /// Read the header of the member at the beginning of `reader`.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self> {
+        let mut gzip_reader = GzipReader::new(reader);
+        match gzip_reader.get_header() {
+            Some(header) => gzip_reader.parse_header(&header?),
+            None => bail!(ErrorKind::TruncatedInput),
+        }
+    }
+
+    pub fn extra_subfields(&self) -> ExtraSubfields<'_> {
+        ExtraSubfields::new(self.extra.as_deref().unwrap_or_default())
+    }
+
+    /// The stored name reduced to its last path component, so that it cannot
+    /// point outside of the directory it is extracted to.
+    pub fn file_name(&self) -> Option<&str> {
+        let name = self.name.as_deref()?.rsplit(['/', '\\']).next()?;
+        match name {
+            "" | "." | ".." => None,
+            name => Some(name),
+        }
+    }
+
     pub fn crc16(&self) -> u16 {
         let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
         let mut digest = crc.digest();
@@ -63,6 +111,53 @@ impl MemberHeader {
         (digest.finalize() & 0xffff) as u16
     }
 
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&[ID1, ID2, self.compression_method.into(), self.flags().0])?;
+        writer.write_all(&self.modification_time.to_le_bytes())?;
+        writer.write_all(&[self.extra_flags, self.os])?;
+
+        if let Some(extra) = &self.extra {
+            writer.write_all(&(extra.len() as u16).to_le_bytes())?;
+            writer.write_all(extra)?;
+        }
+
+        if let Some(name) = &self.name {
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&[0])?;
+        }
+
+        if let Some(comment) = &self.comment {
+            writer.write_all(comment.as_bytes())?;
+            writer.write_all(&[0])?;
+        }
+
+        if self.has_crc {
+            writer.write_all(&self.crc16().to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Name of the operating system the member was created on, see RFC 1952, section 2.3.1.
+    pub fn os_name(&self) -> &'static str {
+        match self.os {
+            0 => "FAT",
+            1 => "Amiga",
+            2 => "VMS",
+            3 => "Unix",
+            4 => "VM/CMS",
+            5 => "Atari",
+            6 => "HPFS",
+            7 => "Macintosh",
+            8 => "Z-System",
+            9 => "CP/M",
+            10 => "TOPS-20",
+            11 => "NTFS",
+            12 => "QDOS",
+            13 => "Acorn",
+            _ => "unknown",
+        }
+    }
+
     pub fn flags(&self) -> MemberFlags {
         let mut flags = MemberFlags(0);
         flags.set_is_text(self.is_text);
@@ -105,6 +200,12 @@ impl From<CompressionMethod> for u8 {
 #[derive(Debug)]
 pub struct MemberFlags(u8);
 
+impl From<u8> for MemberFlags {
+    fn from(value: u8) -> Self {
+        Self(value)
+    }
+}
+
 #[allow(unused)]
 impl MemberFlags {
     fn bit(&self, n: u8) -> bool {
@@ -160,53 +261,222 @@ impl MemberFlags {
     }
 }
 
+/// Check whether the fixed part of a member header looks sane, e.g. when
+/// searching for the next member in corrupted data.
+pub fn is_plausible_header(header: &[u8]) -> bool {
+    header.len() >= 10
+        && header[0] == ID1
+        && header[1] == ID2
+        && header[2] == CM_DEFLATE
+        && header[3] & RESERVED_FLAGS == 0
+        && matches!(header[8], 0 | 2 | 4)
+        && (header[9] <= MAX_KNOWN_OS || header[9] == OS_UNKNOWN)
+}
+
+/// Length of the member header at the start of `data`, or None if `data` ends
+/// before it. Only the fields needed to find its end are checked.
+#[cfg(feature = "tokio")]
+pub fn header_len(data: &[u8]) -> Option<usize> {
+    let flags = MemberFlags::from(*data.get(3)?);
+    let mut len = 10;
+    if flags.has_extra() {
+        let extra_len = data.get(len..len + 2)?;
+        len += 2 + u16::from_le_bytes([extra_len[0], extra_len[1]]) as usize;
+    }
+    for has_field in [flags.has_name(), flags.has_comment()] {
+        if has_field {
+            len += data.get(len..)?.iter().position(|&byte| byte == 0)? + 1;
+        }
+    }
+    if flags.has_crc() {
+        len += 2;
+    }
+    (len <= data.len()).then_some(len)
+}
+
+/// Find the BGZF block size (the total size of the member minus one) in the extra field.
+pub fn bgzf_block_size(extra: &[u8]) -> Option<u16> {
+    ExtraSubfields::new(extra)
+        .map_while(|subfield| subfield.ok())
+        .find_map(|subfield| subfield.bgzf_block_size())
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
-#[derive(Debug)]
+/// Subfield of the FEXTRA header field, see RFC 1952, section 2.3.1.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtraSubfield<'a> {
+    /// The subfield ID, SI1 and SI2.
+    pub id: [u8; 2],
+    pub data: &'a [u8],
+}
+
+impl<'a> ExtraSubfield<'a> {
+    /// Size of the BGZF member minus one, from the "BC" subfield.
+    pub fn bgzf_block_size(&self) -> Option<u16> {
+        match (&self.id, self.data) {
+            (b"BC", &[low, high]) => Some(u16::from_le_bytes([low, high])),
+            _ => None,
+        }
+    }
+
+    /// Random access table of dictzip and Apache mod_deflate, from the "RA" subfield.
+    pub fn dictzip_chunks(&self) -> Option<DictzipChunks> {
+        if &self.id != b"RA" || self.data.len() < 6 {
+            return None;
+        }
+        let mut fields = self
+            .data
+            .chunks_exact(2)
+            .map(|field| u16::from_le_bytes([field[0], field[1]]));
+        let (version, chunk_len, chunk_count) = (fields.next()?, fields.next()?, fields.next()?);
+        if self.data.len() != 6 + 2 * chunk_count as usize {
+            return None;
+        }
+        Some(DictzipChunks {
+            version,
+            chunk_len,
+            compressed_sizes: fields.collect(),
+        })
+    }
+}
+
+/// Dictzip random access table: the data is compressed in chunks of `chunk_len`
+/// bytes, each flushed to a byte boundary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DictzipChunks {
+    pub version: u16,
+    pub chunk_len: u16,
+    pub compressed_sizes: Vec<u16>,
+}
+
+/// Iterator over the subfields of the FEXTRA field, which fails if their lengths
+/// do not add up to the length of the field.
+pub struct ExtraSubfields<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> ExtraSubfields<'a> {
+    pub fn new(extra: &'a [u8]) -> Self {
+        Self { rest: extra }
+    }
+}
+
+impl<'a> Iterator for ExtraSubfields<'a> {
+    type Item = Result<ExtraSubfield<'a>, ErrorKind>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        let rest = std::mem::take(&mut self.rest);
+        if rest.len() < 4 {
+            return Some(Err(ErrorKind::InvalidHeader("extra subfield header")));
+        }
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        match rest.get(4..4 + len) {
+            Some(data) => {
+                self.rest = &rest[4 + len..];
+                Some(Ok(ExtraSubfield {
+                    id: [rest[0], rest[1]],
+                    data,
+                }))
+            }
+            None => Some(Err(ErrorKind::InvalidHeader("extra subfield length"))),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug)]
 pub struct MemberFooter {
     pub data_crc32: u32,
     pub data_size: u32,
 }
 
+impl MemberFooter {
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<Self> {
+        debug!("read footer");
+        let mut footer = [0_u8; 8];
+        reader.read_exact(&mut footer)?;
+        Ok(Self {
+            data_crc32: (&footer[0..4]).read_u32::<LittleEndian>()?,
+            data_size: (&footer[4..8]).read_u32::<LittleEndian>()?,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.data_crc32.to_le_bytes())?;
+        writer.write_all(&self.data_size.to_le_bytes())?;
+        Ok(())
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub struct GzipReader<T> {
     reader: T,
+    max_field_len: usize,
 }
 
 impl<T: BufRead> GzipReader<T> {
     pub fn new(reader: T) -> Self {
-        Self { reader }
+        Self {
+            reader,
+            max_field_len: usize::MAX,
+        }
+    }
+
+    /// Limit the length of the FEXTRA, FNAME and FCOMMENT fields.
+    pub fn set_max_field_len(&mut self, len: usize) {
+        self.max_field_len = len;
+    }
+
+    /// Read a zero-terminated field, without the terminating zero.
+    fn read_field(&mut self) -> Result<Vec<u8>> {
+        let limit = self.max_field_len.saturating_add(1) as u64;
+        let mut data = Vec::new();
+        self.reader.by_ref().take(limit).read_until(0, &mut data)?;
+        if data.last() == Some(&0) {
+            data.pop();
+            return Ok(data);
+        }
+        match data.len() as u64 == limit {
+            true => bail!(ErrorKind::LimitExceeded(Limit::HeaderField(
+                self.max_field_len
+            ))),
+            false => bail!(ErrorKind::TruncatedInput),
+        }
     }
 
     pub fn get_header(&mut self) -> Option<Result<[u8; 10]>> {
-        let mut header = [0_u8; 10];
         debug!("begin reading");
-        match self.reader.read(&mut header) {
-            Ok(sz) => {
-                if sz == 0 {
-                    return None;
-                } else if sz < 10 {
-                    Some(anyhow!("unexpected eof"));
-                }
-            }
-            Err(err) => {
-                return Some(Err(anyhow!(err)));
-            }
+        match self.reader.fill_buf() {
+            Ok([]) => return None,
+            Ok(_) => (),
+            Err(err) => return Some(Err(anyhow!(err))),
+        }
+        let mut header = [0_u8; 10];
+        if let Err(err) = self.reader.read_exact(&mut header) {
+            return Some(Err(anyhow!(err)));
         }
         Some(Ok(header))
     }
 
-    pub fn parse_header(mut self, header: &[u8]) -> Result<(MemberHeader, MemberReader<T>)> {
+    pub fn parse_header(&mut self, header: &[u8]) -> Result<MemberHeader> {
+        if header.len() < 10 {
+            bail!(ErrorKind::TruncatedInput);
+        }
         if header[0] != ID1 || header[1] != ID2 {
-            return Err(anyhow!("wrong id values"));
+            bail!(ErrorKind::BadMagic);
         }
-        if let CompressionMethod::Unknown(_) = CompressionMethod::from(header[2]) {
-            return Err(anyhow!("unsupported compression method"));
+        if let CompressionMethod::Unknown(method) = CompressionMethod::from(header[2]) {
+            bail!(ErrorKind::UnsupportedMethod(method));
         }
 
         let (fl, extra_flags, os) = (MemberFlags(header[3]), header[8], header[9]);
-        let modification_time = (&header[4..8]).read_u32::<LittleEndian>().unwrap();
+        let modification_time = (&header[4..8]).read_u32::<LittleEndian>()?;
 
         let mut extra = None;
 
@@ -214,6 +484,11 @@ impl<T: BufRead> GzipReader<T> {
             let mut len_extra_fields_bytes = [0_u8, 2];
             self.reader.read_exact(&mut len_extra_fields_bytes)?;
             let len_extra_fields = u16::from_le_bytes(len_extra_fields_bytes);
+            if len_extra_fields as usize > self.max_field_len {
+                bail!(ErrorKind::LimitExceeded(Limit::HeaderField(
+                    self.max_field_len
+                )));
+            }
             let mut data_extra = Vec::new();
             data_extra.resize(len_extra_fields as usize, 0_u8);
             self.reader.read_exact(data_extra.as_mut_slice())?;
@@ -222,19 +497,19 @@ impl<T: BufRead> GzipReader<T> {
 
         let mut name = None;
         if fl.has_name() {
-            let mut data = Vec::new();
-            self.reader.read_until(b'\x00', &mut data)?;
-            let str_data = str::from_utf8(&data)?;
-            name = Some(str_data.to_string());
+            let data = self.read_field()?;
+            let name_str =
+                str::from_utf8(&data).map_err(|_| ErrorKind::InvalidHeader("file name"))?;
+            name = Some(name_str.to_string());
         }
 
         let mut comment = None;
 
         if fl.has_comment() {
-            let mut data = Vec::new();
-            self.reader.read_until(b'\x00', &mut data)?;
-            let str_data = str::from_utf8(&data)?;
-            comment = Some(str_data.to_string());
+            let data = self.read_field()?;
+            let comment_str =
+                str::from_utf8(&data).map_err(|_| ErrorKind::InvalidHeader("comment"))?;
+            comment = Some(comment_str.to_string());
         }
 
         let has_crc = fl.has_crc();
@@ -257,37 +532,102 @@ impl<T: BufRead> GzipReader<T> {
             is_text: fl.is_text(),
         };
         if has_crc && crc16 != res.crc16() {
-            return Err(anyhow!("header crc16 check failed"));
+            bail!(ErrorKind::HeaderCrcMismatch {
+                expected: crc16,
+                actual: res.crc16(),
+            });
         }
-        Ok((res, MemberReader { inner: self.reader }))
+        Ok(res)
     }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
 
-pub struct MemberReader<T> {
-    inner: T,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() -> Result<()> {
+        let mut header = MemberHeader::with_level(9);
+        header.name = Some("notes.txt".to_string());
+        header.comment = Some("comment".to_string());
+        header.modification_time = 1700000000;
+        header.has_crc = true;
+        let mut written = Vec::new();
+        header.write(&mut written)?;
+
+        let read = MemberHeader::read(written.as_slice())?;
+        assert_eq!(read.name.as_deref(), Some("notes.txt"));
+        assert_eq!(read.comment.as_deref(), Some("comment"));
+        assert_eq!(read.modification_time, 1700000000);
+        assert_eq!(read.extra_flags, 2);
+        Ok(())
+    }
 
-impl<T: BufRead> MemberReader<T> {
-    pub fn inner_mut(&mut self) -> &mut T {
-        &mut self.inner
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn header_len() -> Result<()> {
+        let mut header = MemberHeader::with_level(6);
+        header.name = Some("notes.txt".to_string());
+        header.extra = Some(vec![b'x', b'y', 0, 0]);
+        header.has_crc = true;
+        let mut written = Vec::new();
+        header.write(&mut written)?;
+        assert_eq!(super::header_len(&written), Some(written.len()));
+        assert_eq!(super::header_len(&written[..written.len() - 1]), None);
+        assert_eq!(super::header_len(&written[..5]), None);
+        Ok(())
     }
 
-    pub fn read_footer(mut self) -> Result<(MemberFooter, GzipReader<T>)> {
-        debug!("read footer");
-        let mut footer = [0_u8; 8];
-        self.inner.read_exact(&mut footer)?;
-        let (data_crc32, data_size) = (
-            (&footer[0..4]).read_u32::<LittleEndian>()?,
-            (&footer[4..8]).read_u32::<LittleEndian>()?,
+    #[test]
+    fn extra_subfields() {
+        let extra = [
+            b'B', b'C', 2, 0, 0x1b, 0x01, b'R', b'A', 10, 0, 1, 0, 0x00, 0x80, 2, 0, 0x34, 0x12,
+            0x78, 0x56, b'x', b'y', 0, 0,
+        ];
+        let subfields: Vec<_> = ExtraSubfields::new(&extra)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(subfields.len(), 3);
+        assert_eq!(subfields[0].bgzf_block_size(), Some(0x011b));
+        assert_eq!(subfields[1].bgzf_block_size(), None);
+        assert_eq!(
+            subfields[1].dictzip_chunks(),
+            Some(DictzipChunks {
+                version: 1,
+                chunk_len: 0x8000,
+                compressed_sizes: vec![0x1234, 0x5678],
+            })
         );
-        Ok((
-            MemberFooter {
-                data_crc32,
-                data_size,
-            },
-            GzipReader::new(self.inner),
-        ))
+        assert_eq!(subfields[2].id, *b"xy");
+        assert_eq!(subfields[2].data, b"");
+        assert_eq!(bgzf_block_size(&extra), Some(0x011b));
+
+        let mut subfields = ExtraSubfields::new(&extra[..9]);
+        assert!(subfields.next().unwrap().is_ok());
+        assert!(matches!(
+            subfields.next(),
+            Some(Err(ErrorKind::InvalidHeader(_)))
+        ));
+        assert!(subfields.next().is_none());
+        assert!(ExtraSubfields::new(&extra[..2]).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn file_name() {
+        let mut header = MemberHeader::with_level(6);
+        for (name, expected) in [
+            ("notes.txt", Some("notes.txt")),
+            ("../../etc/passwd", Some("passwd")),
+            ("/tmp/x", Some("x")),
+            ("C:\\Users\\x.txt", Some("x.txt")),
+            ("dir/..", None),
+            ("dir/", None),
+            ("", None),
+        ] {
+            header.name = Some(name.to_string());
+            assert_eq!(header.file_name(), expected, "{}", name);
+        }
     }
 }
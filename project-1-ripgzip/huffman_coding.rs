@@ -1,11 +1,18 @@
 #![forbid(unsafe_code)]
 
-use std::{collections::HashMap, convert::TryFrom, io::BufRead};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    convert::TryFrom,
+    io::{self, BufRead, Write},
+};
 
 use anyhow::{anyhow, bail, Result};
 use log::debug;
 
 use crate::bit_reader::{BitReader, BitSequence};
+use crate::bit_writer::BitWriter;
+use crate::error::ErrorKind;
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -23,8 +30,11 @@ fn find_sizes<T: BufRead>(
             }
             TreeCodeToken::CopyPrev => {
                 let num_copies = bit_reader.read_bits(2)?.bits() + 3;
+                let prev = *sizes.last().ok_or(ErrorKind::InvalidCodeLengths(
+                    "repeat without a previous length",
+                ))?;
                 for _ in 0..num_copies {
-                    sizes.push(*sizes.last().unwrap());
+                    sizes.push(prev);
                 }
             }
             TreeCodeToken::RepeatZero { base, extra_bits } => {
@@ -33,16 +43,29 @@ fn find_sizes<T: BufRead>(
             }
         }
     }
+    if sizes.len() > num_tokens {
+        bail!(ErrorKind::InvalidCodeLengths("repeat past the last length"));
+    }
     Ok(sizes)
 }
 
-pub fn decode_litlen_distance_trees<T: BufRead>(
+/// Read the code lengths of the litlen and distance trees of a dynamic block,
+/// checking that they describe valid codes with at most `max_distance_codes`
+/// distance codes.
+pub fn read_code_lengths<T: BufRead>(
     bit_reader: &mut BitReader<T>,
-) -> Result<(HuffmanCoding<LitLenToken>, HuffmanCoding<DistanceToken>)> {
+    max_distance_codes: usize,
+) -> Result<(Vec<u8>, Vec<u8>)> {
     let hlit = bit_reader.read_bits(5)?.bits() + 257;
     let hdist = bit_reader.read_bits(5)?.bits() + 1;
     let hclen = bit_reader.read_bits(4)?.bits() + 4;
     debug!("hlit {}, hdist{}, hclen {}", hlit, hdist, hclen);
+    if hlit > 286 {
+        bail!(ErrorKind::InvalidCodeLengths("too many litlen codes"));
+    }
+    if hdist as usize > max_distance_codes {
+        bail!(ErrorKind::InvalidCodeLengths("too many distance codes"));
+    }
     let mapper = [
         16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
     ];
@@ -51,17 +74,43 @@ pub fn decode_litlen_distance_trees<T: BufRead>(
         code_len[mapper[ind as usize]] = bit_reader.read_bits(3)?.bits() as u8;
     }
 
+    check_complete(&code_len, "incomplete code length code")?;
     let encoder_tree = HuffmanCoding::<TreeCodeToken>::from_lengths(&code_len)?;
 
-    let litlen_sizes = find_sizes(bit_reader, hlit as usize, &encoder_tree)?;
+    // The lengths of both trees form a single sequence, so repeats may cross
+    // from the litlen lengths into the distance ones.
+    let mut litlen_sizes = find_sizes(bit_reader, (hlit + hdist) as usize, &encoder_tree)?;
+    let distance_sizes = litlen_sizes.split_off(hlit as usize);
 
-    let distance_sizes = find_sizes(bit_reader, hdist as usize, &encoder_tree)?;
-
-    let litlen_coder = HuffmanCoding::<LitLenToken>::from_lengths(litlen_sizes.as_slice())?;
-    let distance_coder = HuffmanCoding::<DistanceToken>::from_lengths(distance_sizes.as_slice())?;
-    Ok((litlen_coder, distance_coder))
+    if litlen_sizes[256] == 0 {
+        bail!(ErrorKind::InvalidCodeLengths("missing end of block code"));
+    }
+    check_complete(&litlen_sizes, "incomplete litlen code")?;
+    // Blocks without matches need no distance code, and a single distance
+    // code is encoded with one bit (RFC 1951, section 3.2.7).
+    let distance_count = distance_sizes.iter().filter(|&&len| len > 0).count();
+    match (distance_count, distance_sizes.iter().max()) {
+        (0, _) | (1, Some(1)) => (),
+        _ => check_complete(&distance_sizes, "incomplete distance code")?,
+    }
+    Ok((litlen_sizes, distance_sizes))
 }
 
+// See RFC 1951, section 3.2.6. Litlen codes 286, 287 and distance codes 30, 31
+// never occur in deflate data, but they still take part in the code construction.
+// Deflate64 uses the distance codes 30 and 31.
+pub const FIXED_LITLEN_LENGTHS: [u8; 288] = {
+    let mut lengths = [8_u8; 288];
+    let mut ind = 144;
+    while ind < 280 {
+        lengths[ind] = if ind < 256 { 9 } else { 7 };
+        ind += 1;
+    }
+    lengths
+};
+
+pub const FIXED_DISTANCE_LENGTHS: [u8; 32] = [5; 32];
+
 ////////////////////////////////////////////////////////////////////////////////
 
 #[derive(Clone, Copy, Debug)]
@@ -270,67 +319,323 @@ impl TryFrom<HuffmanCodeWord> for DistanceToken {
 ////////////////////////////////////////////////////////////////////////////////
 
 const MAX_BITS: usize = 15;
+const PRIMARY_BITS: u8 = 9;
+/// Kraft sum of a complete code, see `kraft_sum`.
+const KRAFT_COMPLETE: usize = 1 << MAX_BITS;
+
+/// Sum of 2^-len over the used codes, in units of 2^-MAX_BITS. Codes are
+/// over-subscribed above `KRAFT_COMPLETE` and incomplete below.
+fn kraft_sum(code_lengths: &[u8]) -> usize {
+    code_lengths
+        .iter()
+        .filter(|&&len| len > 0)
+        .map(|&len| KRAFT_COMPLETE >> len.min(MAX_BITS as u8))
+        .sum()
+}
+
+fn check_complete(code_lengths: &[u8], incomplete: &'static str) -> Result<()> {
+    match kraft_sum(code_lengths).cmp(&KRAFT_COMPLETE) {
+        Ordering::Greater => bail!(ErrorKind::InvalidCodeLengths("over-subscribed code")),
+        Ordering::Less => bail!(ErrorKind::InvalidCodeLengths(incomplete)),
+        Ordering::Equal => Ok(()),
+    }
+}
 
 pub struct HuffmanCodeWord(pub u16);
 
+#[derive(Clone, Copy)]
+enum TableEntry<T> {
+    Invalid,
+    Symbol { value: T, len: u8 },
+    // Codes longer than PRIMARY_BITS continue in a secondary table, indexed
+    // by the next `bits` bits.
+    Link { offset: usize, bits: u8 },
+}
+
 pub struct HuffmanCoding<T> {
-    map: HashMap<BitSequence, T>,
+    // The primary table occupies the first 2^PRIMARY_BITS entries and is indexed
+    // by the next bits of the stream, followed by the secondary tables.
+    table: Vec<TableEntry<T>>,
+    // Length of the longest code, after which a sequence of bits cannot match.
+    max_len: u8,
 }
 
 impl<T> HuffmanCoding<T>
 where
     T: Copy + TryFrom<HuffmanCodeWord, Error = anyhow::Error>,
 {
+    /// Build the decoding table, with the symbols converted by `TryFrom`.
+    pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
+        Self::from_lengths_with(code_lengths, |symbol| {
+            T::try_from(HuffmanCodeWord(symbol)).ok()
+        })
+    }
+}
+
+impl<T: Copy> HuffmanCoding<T> {
     #[allow(unused)]
     pub fn decode_symbol(&self, seq: BitSequence) -> Option<T> {
-        self.map.get(&seq).copied()
+        match self.lookup(seq.reverse()) {
+            Some((value, len)) if len == seq.len() => Some(value),
+            _ => None,
+        }
     }
 
     pub fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
+        let seq = bit_reader.peek_bits(MAX_BITS as u8)?;
+        match self.lookup(seq) {
+            Some((value, len)) => {
+                bit_reader.consume(len)?;
+                Ok(value)
+            }
+            None if seq.len() >= self.max_len => bail!(ErrorKind::InvalidCode),
+            // Not enough bits are buffered to look the code up at once.
+            None => self.read_symbol_bitwise(bit_reader),
+        }
+    }
+
+    fn read_symbol_bitwise<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<T> {
         let mut result_symbol = BitSequence::new(0, 0);
-        loop {
+        while result_symbol.len() < self.max_len {
             match bit_reader.read_bits(1) {
                 Ok(seq) => {
                     result_symbol = seq.concat(result_symbol);
                 }
                 Err(err) => {
                     debug!("{}", err);
-                    bail!(err)
+                    return Err(err.into());
                 }
             }
             if let Some(val) = self.decode_symbol(result_symbol) {
                 return Ok(val);
             }
         }
+        bail!(ErrorKind::InvalidCode)
     }
 
-    pub fn from_lengths(code_lengths: &[u8]) -> Result<Self> {
-        let mut map: HashMap<BitSequence, T> = HashMap::new();
-        let mut bl_count: [usize; 256] = [0; 256];
-        let mut next_code: [u16; MAX_BITS + 1] = [0; MAX_BITS + 1];
-        for &len in code_lengths {
-            if len > 0 {
-                bl_count[len as usize] += 1;
+    /// Find the symbol whose code is a prefix of `seq`, given in stream order.
+    fn lookup(&self, seq: BitSequence) -> Option<(T, u8)> {
+        let index = (seq.bits() & ((1 << PRIMARY_BITS) - 1)) as usize;
+        let entry = match self.table[index] {
+            TableEntry::Link { offset, bits } => {
+                let index = (seq.bits() >> PRIMARY_BITS) & ((1 << bits) - 1);
+                self.table[offset + index as usize]
             }
+            entry => entry,
+        };
+        match entry {
+            TableEntry::Symbol { value, len } if len <= seq.len() => Some((value, len)),
+            _ => None,
         }
-        let mut code = 0;
-        for bits in 1..(MAX_BITS + 1) {
-            code = (code + bl_count[bits - 1]) << 1;
-            next_code[bits] = code as u16;
+    }
+
+    /// Build the decoding table, with `symbol_value` giving the value of each symbol.
+    /// Incomplete codes are accepted, e.g. the fixed distance code, and bit
+    /// sequences outside of them are invalid codes.
+    pub fn from_lengths_with(
+        code_lengths: &[u8],
+        symbol_value: impl Fn(u16) -> Option<T>,
+    ) -> Result<Self> {
+        let max_len = code_lengths.iter().copied().max().unwrap_or(0);
+        if max_len as usize > MAX_BITS {
+            bail!(ErrorKind::InvalidCodeLengths("length over 15"));
+        }
+        if kraft_sum(code_lengths) > KRAFT_COMPLETE {
+            bail!(ErrorKind::InvalidCodeLengths("over-subscribed code"));
+        }
+        let mut table = vec![TableEntry::Invalid; 1 << PRIMARY_BITS];
+        let mut long_codes = Vec::new();
+        for (ind, code) in canonical_codes(code_lengths).into_iter().enumerate() {
+            // Symbols without a value (e.g. litlen 286, 287) still occupy a code.
+            if let (Some(code), Some(value)) = (code, symbol_value(ind as u16)) {
+                let (code, len) = (code.reverse().bits() as usize, code.len());
+                if len <= PRIMARY_BITS {
+                    for index in (code..1 << PRIMARY_BITS).step_by(1 << len) {
+                        table[index] = TableEntry::Symbol { value, len };
+                    }
+                } else {
+                    long_codes.push((code, len, value));
+                }
+            }
         }
 
-        for (ind, &code_len) in code_lengths.iter().enumerate() {
-            if code_len != 0 {
-                let value = T::try_from(HuffmanCodeWord(ind as u16));
-                map.insert(
-                    BitSequence::new(next_code[code_len as usize], code_len),
-                    value.unwrap(),
-                );
-                next_code[code_len as usize] += 1;
+        // Each primary prefix of the long codes gets one secondary table,
+        // sized for the longest code with that prefix.
+        let primary_mask = (1 << PRIMARY_BITS) - 1;
+        for &(code, _, _) in &long_codes {
+            let prefix = code & primary_mask;
+            if let TableEntry::Invalid = table[prefix] {
+                let bits = long_codes
+                    .iter()
+                    .filter(|&&(other, _, _)| other & primary_mask == prefix)
+                    .map(|&(_, len, _)| len - PRIMARY_BITS)
+                    .max()
+                    .unwrap_or(0);
+                table[prefix] = TableEntry::Link {
+                    offset: table.len(),
+                    bits,
+                };
+                table.resize(table.len() + (1 << bits), TableEntry::Invalid);
             }
         }
-        Ok(Self { map })
+        for (code, len, value) in long_codes {
+            if let TableEntry::Link { offset, bits } = table[code & primary_mask] {
+                let suffix = code >> PRIMARY_BITS;
+                for index in (suffix..1 << bits).step_by(1 << (len - PRIMARY_BITS)) {
+                    table[offset + index] = TableEntry::Symbol { value, len };
+                }
+            }
+        }
+        Ok(Self { table, max_len })
+    }
+}
+
+/// Assign the codes to the symbols as described in RFC 1951, section 3.2.2.
+/// The lengths must not exceed `MAX_BITS`.
+fn canonical_codes(code_lengths: &[u8]) -> Vec<Option<BitSequence>> {
+    let mut bl_count: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
+    let mut next_code: [usize; MAX_BITS + 1] = [0; MAX_BITS + 1];
+    for &len in code_lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
     }
+    let mut code = 0;
+    for bits in 1..(MAX_BITS + 1) {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    let mut codes = Vec::with_capacity(code_lengths.len());
+    for &code_len in code_lengths {
+        if code_len != 0 {
+            codes.push(Some(BitSequence::new(
+                next_code[code_len as usize] as u16,
+                code_len,
+            )));
+            next_code[code_len as usize] += 1;
+        } else {
+            codes.push(None);
+        }
+    }
+    codes
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct HuffmanEncoder {
+    // Codes are stored bit-reversed, so that they can be written LSB first.
+    codes: Vec<BitSequence>,
+}
+
+impl HuffmanEncoder {
+    pub fn from_lengths(code_lengths: &[u8]) -> Self {
+        let codes = canonical_codes(code_lengths)
+            .into_iter()
+            .map(|code| match code {
+                Some(code) => code.reverse(),
+                None => BitSequence::new(0, 0),
+            })
+            .collect();
+        Self { codes }
+    }
+
+    pub fn code_len(&self, symbol: u16) -> u8 {
+        self.codes[symbol as usize].len()
+    }
+
+    pub fn write_symbol<U: Write>(
+        &self,
+        bit_writer: &mut BitWriter<U>,
+        symbol: u16,
+    ) -> io::Result<()> {
+        bit_writer.write_bits(self.codes[symbol as usize])
+    }
+}
+
+/// Build length-limited Huffman code lengths for the given symbol frequencies.
+pub fn lengths_from_frequencies(freqs: &[usize], max_bits: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|&len| len <= max_bits) {
+            return lengths;
+        }
+        // Flatten the distribution until the tree is shallow enough.
+        for freq in freqs.iter_mut().filter(|freq| **freq > 0) {
+            *freq = freq.div_ceil(2);
+        }
+    }
+}
+
+fn huffman_lengths(freqs: &[usize]) -> Vec<u8> {
+    let mut lengths = vec![0_u8; freqs.len()];
+    let used: Vec<usize> = (0..freqs.len()).filter(|&ind| freqs[ind] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            // A single code would be incomplete, so pair it with a dummy one.
+            lengths[used[0]] = 1;
+            lengths[if used[0] == 0 { 1 } else { 0 }] = 1;
+            return lengths;
+        }
+        _ => (),
+    }
+
+    let mut parents = vec![usize::MAX; used.len()];
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = used
+        .iter()
+        .enumerate()
+        .map(|(node, &ind)| Reverse((freqs[ind], node)))
+        .collect();
+    while let (Some(Reverse((freq_a, a))), Some(Reverse((freq_b, b)))) = (heap.pop(), heap.pop()) {
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((freq_a + freq_b, node)));
+    }
+
+    for (node, &ind) in used.iter().enumerate() {
+        let mut depth = 0;
+        let mut current = node;
+        while parents[current] != usize::MAX {
+            current = parents[current];
+            depth += 1;
+        }
+        lengths[ind] = depth;
+    }
+    lengths
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Find the litlen symbol and the extra bits encoding a match length.
+pub fn encode_length(len: u16) -> (u16, BitSequence) {
+    for symbol in (257..=285).rev() {
+        if let Ok(LitLenToken::Length { base, extra_bits }) =
+            LitLenToken::try_from(HuffmanCodeWord(symbol))
+        {
+            if base <= len {
+                return (symbol, BitSequence::new(len - base, extra_bits));
+            }
+        }
+    }
+    panic!("match length {} is too small", len);
+}
+
+/// Find the distance symbol and the extra bits encoding a match distance.
+pub fn encode_distance(dist: u16) -> (u16, BitSequence) {
+    for symbol in (0..30).rev() {
+        if let Ok(DistanceToken { base, extra_bits }) =
+            DistanceToken::try_from(HuffmanCodeWord(symbol))
+        {
+            if base <= dist {
+                return (symbol, BitSequence::new(dist - base, extra_bits));
+            }
+        }
+    }
+    panic!("match distance {} is too small", dist);
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -338,6 +643,8 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::deflate::{BlockDecoder, Deflate, CODE_LENGTH_ORDER};
+    use std::collections::HashMap;
 
     #[derive(Clone, Copy, Debug, PartialEq)]
     struct Value(u16);
@@ -390,6 +697,129 @@ mod tests {
         Ok(())
     }
 
+    fn code_lengths_error<T>(result: Result<T>) -> Option<&'static str> {
+        match result.err()?.downcast_ref::<ErrorKind>() {
+            Some(ErrorKind::InvalidCodeLengths(what)) => Some(what),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn invalid_lengths() {
+        let from_lengths = HuffmanCoding::<Value>::from_lengths;
+        assert_eq!(
+            code_lengths_error(from_lengths(&[1, 17])),
+            Some("length over 15")
+        );
+        assert_eq!(
+            code_lengths_error(from_lengths(&[1, 2, 2, 2])),
+            Some("over-subscribed code")
+        );
+        assert!(from_lengths(&[1, 2]).is_ok());
+    }
+
+    /// Encode the start of a dynamic block header with a complete code length
+    /// code, followed by code length symbols with the values of their extra bits.
+    fn dynamic_header(hlit: u16, hdist: u16, symbols: &[(u16, u16)]) -> Result<Vec<u8>> {
+        let code_len: Vec<u8> = (0..19)
+            .map(|symbol| if symbol < 13 { 4 } else { 5 })
+            .collect();
+        let encoder = HuffmanEncoder::from_lengths(&code_len);
+        let mut writer = BitWriter::new(Vec::new());
+        writer.write_bits(BitSequence::new(hlit - 257, 5))?;
+        writer.write_bits(BitSequence::new(hdist - 1, 5))?;
+        writer.write_bits(BitSequence::new(19 - 4, 4))?;
+        for symbol in CODE_LENGTH_ORDER {
+            writer.write_bits(BitSequence::new(code_len[symbol] as u16, 3))?;
+        }
+        for &(symbol, extra) in symbols {
+            encoder.write_symbol(&mut writer, symbol)?;
+            let extra_bits = match symbol {
+                16 => 2,
+                17 => 3,
+                18 => 7,
+                _ => 0,
+            };
+            writer.write_bits(BitSequence::new(extra, extra_bits))?;
+        }
+        Ok(writer.finish()?)
+    }
+
+    #[test]
+    fn read_code_lengths() -> Result<()> {
+        // Literals 0..=254 get 8 bits, followed by the given lengths.
+        let lengths = |symbols: &[(u16, u16)]| [&vec![(8, 0); 255], symbols].concat();
+        let read = |hlit, hdist, symbols: &[(u16, u16)]| -> Result<(Vec<u8>, Vec<u8>)> {
+            let data = dynamic_header(hlit, hdist, symbols)?;
+            super::read_code_lengths(&mut BitReader::new(data.as_slice()), 30)
+        };
+
+        // Zeros repeated from the litlen lengths into the distance ones.
+        let (litlen_sizes, distance_sizes) = read(259, 2, &lengths(&[(9, 0), (9, 0), (17, 1)]))?;
+        assert_eq!((litlen_sizes.len(), litlen_sizes[256]), (259, 9));
+        assert_eq!(distance_sizes, [0, 0]);
+        let (_, distance_sizes) = read(257, 2, &lengths(&[(9, 0), (9, 0), (0, 0), (1, 0)]))?;
+        assert_eq!(distance_sizes, [0, 1]);
+        // Deflate64 allows 32 distance codes.
+        let data = dynamic_header(257, 32, &lengths(&[(9, 0), (9, 0), (18, 20), (1, 0)]))?;
+        let (_, distance_sizes) =
+            super::read_code_lengths(&mut BitReader::new(data.as_slice()), 32)?;
+        assert_eq!((distance_sizes.len(), distance_sizes[31]), (32, 1));
+
+        let errors = [
+            (287, 1, lengths(&[]), "too many litlen codes"),
+            (257, 31, lengths(&[]), "too many distance codes"),
+            (257, 1, vec![(16, 0)], "repeat without a previous length"),
+            (
+                259,
+                2,
+                lengths(&[(9, 0), (9, 0), (17, 2)]),
+                "repeat past the last length",
+            ),
+            (
+                257,
+                1,
+                lengths(&[(8, 0), (0, 0), (1, 0)]),
+                "missing end of block code",
+            ),
+            (
+                257,
+                1,
+                lengths(&[(0, 0), (9, 0), (1, 0)]),
+                "incomplete litlen code",
+            ),
+            (
+                257,
+                1,
+                lengths(&[(8, 0), (8, 0), (1, 0)]),
+                "over-subscribed code",
+            ),
+            (
+                257,
+                1,
+                lengths(&[(9, 0), (9, 0), (2, 0)]),
+                "incomplete distance code",
+            ),
+        ];
+        for (hlit, hdist, symbols, error) in errors {
+            assert_eq!(code_lengths_error(read(hlit, hdist, &symbols)), Some(error));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn read_symbol_incomplete() -> Result<()> {
+        // Only 0 and 10 are codes, so the input is invalid after two bits.
+        let code = HuffmanCoding::<Value>::from_lengths(&[1, 2])?;
+        let mut reader = BitReader::new(&[0b11_u8][..]);
+        let err = code.read_symbol(&mut reader).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<ErrorKind>(),
+            Some(ErrorKind::InvalidCode)
+        ));
+        Ok(())
+    }
+
     #[test]
     fn read_symbol() -> Result<()> {
         let code = HuffmanCoding::<Value>::from_lengths(&[2, 3, 4, 3, 3, 4, 2])?;
@@ -407,4 +837,132 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn fixed_trees() -> Result<()> {
+        let (lit_len, distance) = Deflate.fixed_trees()?;
+
+        assert!(matches!(
+            lit_len.decode_symbol(BitSequence::new(0b00110000, 8)),
+            Some(LitLenToken::Literal(0)),
+        ));
+        assert!(matches!(
+            lit_len.decode_symbol(BitSequence::new(0b110010000, 9)),
+            Some(LitLenToken::Literal(144)),
+        ));
+        assert!(matches!(
+            lit_len.decode_symbol(BitSequence::new(0b0000000, 7)),
+            Some(LitLenToken::EndOfBlock),
+        ));
+        assert!(matches!(
+            lit_len.decode_symbol(BitSequence::new(0b11000101, 8)),
+            Some(LitLenToken::Length {
+                base: 258,
+                extra_bits: 0
+            }),
+        ));
+        assert!(matches!(
+            distance.decode_symbol(BitSequence::new(0b11101, 5)),
+            Some(DistanceToken {
+                base: 24577,
+                extra_bits: 13
+            }),
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_long_codes() -> Result<()> {
+        let mut lengths = vec![2_u8, 2, 2];
+        lengths.extend(10..=15);
+        lengths.push(15);
+        let code = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        let encoder = HuffmanEncoder::from_lengths(&lengths);
+
+        let mut writer = BitWriter::new(Vec::new());
+        let symbols = [9, 3, 0, 8, 4, 9, 1, 7, 2];
+        for &symbol in &symbols {
+            encoder.write_symbol(&mut writer, symbol)?;
+        }
+        let data = writer.finish()?;
+
+        let mut reader = BitReader::new(data.as_slice());
+        for &symbol in &symbols {
+            assert_eq!(code.read_symbol(&mut reader)?, Value(symbol));
+        }
+        Ok(())
+    }
+
+    /// The decoder before the lookup tables, which reads one bit at a time
+    /// and looks every prefix up in a map. Kept as the baseline of the benchmark.
+    struct HashMapCoding {
+        map: HashMap<BitSequence, Value>,
+    }
+
+    impl HashMapCoding {
+        fn from_lengths(code_lengths: &[u8]) -> Self {
+            let map = canonical_codes(code_lengths)
+                .into_iter()
+                .enumerate()
+                .filter_map(|(ind, code)| Some((code?, Value(ind as u16))))
+                .collect();
+            Self { map }
+        }
+
+        fn read_symbol<U: BufRead>(&self, bit_reader: &mut BitReader<U>) -> Result<Value> {
+            let mut result_symbol = BitSequence::new(0, 0);
+            loop {
+                result_symbol = bit_reader.read_bits(1)?.concat(result_symbol);
+                if let Some(&val) = self.map.get(&result_symbol) {
+                    return Ok(val);
+                }
+            }
+        }
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_read_symbol() -> Result<()> {
+        let mut freqs = [0_usize; 286];
+        for (ind, freq) in freqs.iter_mut().enumerate() {
+            *freq = 1 + (ind * 7919) % 1000;
+        }
+        let lengths = lengths_from_frequencies(&freqs, 15);
+        let code = HuffmanCoding::<Value>::from_lengths(&lengths)?;
+        let hash_map_code = HashMapCoding::from_lengths(&lengths);
+        let encoder = HuffmanEncoder::from_lengths(&lengths);
+
+        let symbol_count = 1 << 22;
+        let mut writer = BitWriter::new(Vec::new());
+        for ind in 0..symbol_count {
+            encoder.write_symbol(&mut writer, (ind * 7 % 286) as u16)?;
+        }
+        let data = writer.finish()?;
+
+        let start = std::time::Instant::now();
+        let mut reader = BitReader::new(data.as_slice());
+        for ind in 0..symbol_count {
+            let symbol = code.read_symbol(&mut reader)?;
+            assert_eq!(symbol, Value((ind * 7 % 286) as u16));
+        }
+        let table_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let mut reader = BitReader::new(data.as_slice());
+        for ind in 0..symbol_count {
+            let symbol = hash_map_code.read_symbol(&mut reader)?;
+            assert_eq!(symbol, Value((ind * 7 % 286) as u16));
+        }
+        let hash_map_time = start.elapsed();
+
+        let megabytes = data.len() as f64 / (1 << 20) as f64;
+        println!(
+            "table: {:.1} MB/s, hash map: {:.1} MB/s",
+            megabytes / table_time.as_secs_f64(),
+            megabytes / hash_map_time.as_secs_f64(),
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,309 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{compress_with_format, Decoder, Error, Format};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const MAGIC: &[u8; 8] = b"RGZINDEX";
+const VERSION: u8 = 1;
+const SIDECAR_EXTENSION: &str = "rgzidx";
+// Checkpoint windows hold at most the 64 KiB history of Deflate64, compressed
+// with stored blocks in the worst case.
+const MAX_WINDOW_SIZE: usize = 1 << 16;
+const MAX_COMPRESSED_WINDOW_SIZE: usize = MAX_WINDOW_SIZE + 1024;
+// Offsets and window length of a checkpoint with an empty window.
+const MIN_CHECKPOINT_SIZE: u64 = 8 + 8 + 4;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A block boundary where decoding can be resumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub uncompressed_offset: u64,
+    /// Position of the block header in the compressed data, in bits.
+    pub bit_offset: u64,
+    /// Up to 32 KiB of the data decompressed before the checkpoint.
+    pub window: Vec<u8>,
+}
+
+/// Checkpoints for random access into compressed data, similar to zran.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Index {
+    format: Format,
+    total_size: u64,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Index {
+    /// Decompress `input` recording a checkpoint every `spacing` decompressed bytes.
+    pub fn build<R: BufRead>(input: R, format: Format, spacing: u64) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(input, format);
+        decoder.record_checkpoints(spacing);
+        let total_size =
+            io::copy(&mut decoder, &mut io::sink()).map_err(|err| decoder.locate(err))?;
+        Ok(Self {
+            format,
+            total_size,
+            checkpoints: decoder.take_checkpoints(),
+        })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Size of the decompressed data.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Serialize the index, compressing the windows with raw deflate.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u8(VERSION)?;
+        writer.write_u8(match self.format {
+            Format::Gzip => 0,
+            Format::Zlib => 1,
+            Format::Raw => 2,
+        })?;
+        writer.write_u64::<LittleEndian>(self.total_size)?;
+        writer.write_u64::<LittleEndian>(self.checkpoints.len() as u64)?;
+        for checkpoint in &self.checkpoints {
+            writer.write_u64::<LittleEndian>(checkpoint.uncompressed_offset)?;
+            writer.write_u64::<LittleEndian>(checkpoint.bit_offset)?;
+            let mut window = Vec::new();
+            compress_with_format(checkpoint.window.as_slice(), &mut window, 6, Format::Raw)?;
+            writer.write_u32::<LittleEndian>(window.len() as u32)?;
+            writer.write_all(&window)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Deserialize an index written by `write`, which may come from an untrusted file.
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("not an index file");
+        }
+        if reader.read_u8()? != VERSION {
+            bail!("unsupported index version");
+        }
+        let format = match reader.read_u8()? {
+            0 => Format::Gzip,
+            1 => Format::Zlib,
+            2 => Format::Raw,
+            _ => bail!("unknown format in index"),
+        };
+        let total_size = reader.read_u64::<LittleEndian>()?;
+        let count = reader.read_u64::<LittleEndian>()?;
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest)?;
+        if count > rest.len() as u64 / MIN_CHECKPOINT_SIZE {
+            bail!("too many checkpoints in index");
+        }
+        let mut reader = rest.as_slice();
+        let mut checkpoints = Vec::new();
+        for _ in 0..count {
+            let uncompressed_offset = reader.read_u64::<LittleEndian>()?;
+            let bit_offset = reader.read_u64::<LittleEndian>()?;
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            if len > MAX_COMPRESSED_WINDOW_SIZE || len > reader.len() {
+                bail!("invalid window length in index");
+            }
+            let (window, tail) = reader.split_at(len);
+            reader = tail;
+            let mut decompressed = Vec::new();
+            Decoder::new(window, Format::Raw)
+                .take(MAX_WINDOW_SIZE as u64 + 1)
+                .read_to_end(&mut decompressed)?;
+            if decompressed.len() > MAX_WINDOW_SIZE {
+                bail!("invalid window in index");
+            }
+            checkpoints.push(Checkpoint {
+                uncompressed_offset,
+                bit_offset,
+                window: decompressed,
+            });
+        }
+        Ok(Self {
+            format,
+            total_size,
+            checkpoints,
+        })
+    }
+}
+
+/// Path of the index file kept next to the compressed file.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(SIDECAR_EXTENSION);
+    PathBuf::from(name)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Decompressed view of `R` supporting `Seek`, which restarts the decoding from
+/// the closest checkpoint of the index.
+pub struct IndexedReader<R> {
+    decoder: Option<Decoder<BufReader<R>>>,
+    index: Index,
+    position: u64,
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// `input` must be positioned at the beginning of the compressed data.
+    pub fn new(input: R, index: Index) -> Self {
+        Self {
+            decoder: Some(Decoder::new(BufReader::new(input), index.format)),
+            index,
+            position: 0,
+        }
+    }
+
+    fn restart(&mut self, checkpoint: Option<&Checkpoint>) -> io::Result<()> {
+        let mut input = match self.decoder.take() {
+            Some(decoder) => decoder.into_inner(),
+            None => return Err(io::Error::other("the previous seek failed")),
+        };
+        self.decoder = Some(match checkpoint {
+            Some(checkpoint) => {
+                input.seek(SeekFrom::Start(checkpoint.bit_offset / 8))?;
+                Decoder::resume(input, self.index.format, checkpoint)?
+            }
+            None => {
+                input.seek(SeekFrom::Start(0))?;
+                Decoder::new(input, self.index.format)
+            }
+        });
+        self.position = checkpoint.map_or(0, |checkpoint| checkpoint.uncompressed_offset);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = match &mut self.decoder {
+            Some(decoder) => decoder,
+            None => return Err(io::Error::other("the previous seek failed")),
+        };
+        let len = decoder.read(buf)?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedReader<R> {
+    /// Seeking past the end of the data stops at the end.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.index.total_size.checked_add_signed(delta),
+        };
+        let target = target.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+
+        let checkpoint = self
+            .index
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| checkpoint.uncompressed_offset <= target)
+            .cloned();
+        let start = checkpoint
+            .as_ref()
+            .map_or(0, |checkpoint| checkpoint.uncompressed_offset);
+        if target < self.position || start > self.position {
+            self.restart(checkpoint.as_ref())?;
+        }
+        let skip = target - self.position;
+        io::copy(&mut self.by_ref().take(skip), &mut io::sink())?;
+        Ok(self.position)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress;
+    use std::io::Cursor;
+
+    #[test]
+    fn seek() -> Result<()> {
+        let data: Vec<u8> = (0..3000000_u32)
+            .map(|i| ((i / 13) ^ (i % 1000 / 3)) as u8)
+            .collect();
+        let mut compressed = Vec::new();
+        compress(&data[..1000000], &mut compressed, 6)?;
+        compress(&data[1000000..], &mut compressed, 1)?;
+
+        let index = Index::build(compressed.as_slice(), Format::Gzip, 1 << 18)?;
+        assert_eq!(index.total_size(), data.len() as u64);
+        assert!(index.checkpoints().len() > 5);
+        let mut serialized = Vec::new();
+        index.write(&mut serialized)?;
+        let index = Index::read(serialized.as_slice())?;
+
+        let mut reader = IndexedReader::new(Cursor::new(compressed), index);
+        let mut buf = vec![0_u8; 5000];
+        for offset in [2500000, 10, 999000, 1800000, 1800001, 2999000] {
+            let len = std::cmp::min(buf.len(), data.len() - offset);
+            assert_eq!(reader.seek(SeekFrom::Start(offset as u64))?, offset as u64);
+            reader.read_exact(&mut buf[..len])?;
+            assert_eq!(buf[..len], data[offset..offset + len]);
+        }
+        assert_eq!(reader.seek(SeekFrom::End(-10))?, data.len() as u64 - 10);
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail)?;
+        assert_eq!(tail, data[data.len() - 10..]);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_index() -> Result<()> {
+        let data: Vec<u8> = (0..300000_u32).map(|i| (i % 1000 / 3) as u8).collect();
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        let index = Index::build(compressed.as_slice(), Format::Gzip, 1 << 16)?;
+        assert!(!index.checkpoints().is_empty());
+        let mut serialized = Vec::new();
+        index.write(&mut serialized)?;
+        assert_eq!(Index::read(serialized.as_slice())?, index);
+
+        let corrupt = |offset: usize, bytes: &[u8]| {
+            let mut corrupted = serialized.clone();
+            corrupted[offset..offset + bytes.len()].copy_from_slice(bytes);
+            Index::read(corrupted.as_slice())
+        };
+        // The checkpoint count, then the window length of the first checkpoint.
+        assert!(corrupt(18, &u64::MAX.to_le_bytes()).is_err());
+        assert!(corrupt(18, &(1_u64 << 40).to_le_bytes()).is_err());
+        assert!(corrupt(42, &u32::MAX.to_le_bytes()).is_err());
+        assert!(Index::read(&serialized[..serialized.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sidecar() {
+        assert_eq!(
+            sidecar_path(Path::new("logs/app.log.gz")),
+            Path::new("logs/app.log.gz.rgzidx")
+        );
+    }
+}
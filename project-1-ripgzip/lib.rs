@@ -1,109 +1,248 @@
 #![forbid(unsafe_code)]
 
-use std::io::{BufRead, Write};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::str::FromStr;
 
-use crate::gzip::GzipReader;
 use anyhow::{bail, Result};
-use bit_reader::BitReader;
-use byteorder::{LittleEndian, ReadBytesExt};
-use deflate::DeflateReader;
-use huffman_coding::decode_litlen_distance_trees;
+use bit_writer::BitWriter;
+use checksum::ChecksumKind;
+use deflate::DeflateWriter;
 use log::debug;
-use tracking_writer::TrackingWriter;
+use lz77::{Level, Lz77Encoder};
+use zlib::ZlibHeader;
 
+#[cfg(feature = "tokio")]
+pub use async_decoder::AsyncDecoder;
+pub use decoder::{BlockEvent, BlockInfo, Decoder, GzipDecoder, Limits, MemberInfo, Progress};
+pub use deflate::{BlockDecoder, CompressionType, Deflate, Deflate64};
+pub use error::{Error, ErrorKind, Limit};
+pub use gzip::{
+    CompressionMethod, DictzipChunks, ExtraSubfield, ExtraSubfields, MemberFooter, MemberHeader,
+};
+pub use huffman_coding::{DistanceToken, LitLenToken};
+pub use index::{sidecar_path, Checkpoint, Index, IndexedReader};
+pub use parallel::decompress_parallel;
+pub use recover::{decompress_recover, SkippedRange};
+pub use speculative::decompress_speculative;
+pub use zip::{DosDateTime, ZipArchive, ZipEntry, ZipEntryReader, ZipMethod};
+
+#[cfg(feature = "tokio")]
+mod async_decoder;
 mod bit_reader;
+mod bit_writer;
+mod checksum;
+mod decoder;
 mod deflate;
+mod error;
 mod gzip;
 mod huffman_coding;
+mod index;
+mod lz77;
+mod parallel;
+mod recover;
+mod speculative;
 mod tracking_writer;
+mod zip;
+mod zlib;
+
+/// Internals exercised by the fuzz targets in `fuzz/`.
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing {
+    pub use crate::bit_reader::BitReader;
+    pub use crate::gzip::GzipReader;
+    pub use crate::huffman_coding::{HuffmanCoding, LitLenToken};
+}
+
+/// Generators of test data shared by the unit tests.
+#[cfg(test)]
+mod test_util {
+    /// Compressible data with matches at many distances.
+    pub fn sample_data(len: u32) -> Vec<u8> {
+        (0..len).map(|i| ((i % 251) ^ (i / 97)) as u8).collect()
+    }
+
+    /// Xorshift32 pseudo-random numbers, reproducible from the seed.
+    pub struct XorShift(u32);
+
+    impl XorShift {
+        pub fn new(seed: u32) -> Self {
+            Self(seed)
+        }
+    }
+
+    impl Iterator for XorShift {
+        type Item = u32;
+
+        fn next(&mut self) -> Option<u32> {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            Some(self.0)
+        }
+    }
+}
+
+const BLOCK_SIZE: usize = 1 << 17;
+
+/// Container around the deflate data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Gzip members (RFC 1952), possibly concatenated.
+    Gzip,
+    /// A zlib stream (RFC 1950).
+    Zlib,
+    /// Raw deflate data (RFC 1951).
+    Raw,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "gzip" => Ok(Self::Gzip),
+            "zlib" => Ok(Self::Zlib),
+            "raw" => Ok(Self::Raw),
+            _ => bail!("unknown format {}", value),
+        }
+    }
+}
+
+pub fn decompress<R: BufRead, W: Write>(input: R, output: W) -> Result<(), Error> {
+    decompress_with_format(input, output, Format::Gzip)
+}
 
-pub fn decompress<R: BufRead, W: Write>(input: R, mut output: W) -> Result<()> {
-    let mut writer = TrackingWriter::new(&mut output);
-    let mut main_reader = GzipReader::new(input);
-    debug!("we are here!");
+pub fn decompress_with_format<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+    format: Format,
+) -> Result<(), Error> {
+    let mut decoder = Decoder::new(input, format);
+    io::copy(&mut decoder, &mut output).map_err(|err| decoder.locate(err))?;
+    Ok(())
+}
+
+/// Compress `input` into a single gzip member. `level` ranges from 1 (fastest)
+/// to 9 (best compression).
+pub fn compress<R: BufRead, W: Write>(input: R, output: W, level: u32) -> Result<()> {
+    compress_with_format(input, output, level, Format::Gzip)
+}
+
+pub fn compress_with_format<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    level: u32,
+    format: Format,
+) -> Result<()> {
+    let header = MemberHeader::with_level(level);
+    compress_member(input, output, level, format, &header)
+}
+
+/// Compress `input` into a single gzip member with the given header, e.g. to
+/// store the file name and modification time.
+pub fn compress_with_header<R: BufRead, W: Write>(
+    input: R,
+    output: W,
+    level: u32,
+    header: &MemberHeader,
+) -> Result<()> {
+    compress_member(input, output, level, Format::Gzip, header)
+}
+
+fn compress_member<R: BufRead, W: Write>(
+    mut input: R,
+    output: W,
+    level: u32,
+    format: Format,
+    header: &MemberHeader,
+) -> Result<()> {
+    let mut output = BufWriter::new(output);
+    let checksum_kind = match format {
+        Format::Gzip => {
+            header.write(&mut output)?;
+            ChecksumKind::Crc32
+        }
+        Format::Zlib => {
+            let header = ZlibHeader {
+                window_info: 7,
+                level: match level {
+                    1 => 0,
+                    2..=5 => 1,
+                    6 => 2,
+                    _ => 3,
+                },
+                dictionary_id: None,
+            };
+            header.write(&mut output)?;
+            ChecksumKind::Adler32
+        }
+        Format::Raw => ChecksumKind::Crc32,
+    };
+
+    let mut checksum = checksum_kind.new_checksum();
+    let mut data_size = 0_u32;
+    let mut encoder = Lz77Encoder::new(Level::new(level));
+    let mut deflate_writer = DeflateWriter::new(BitWriter::new(&mut output));
+    let mut block = Vec::with_capacity(BLOCK_SIZE);
+    let mut tokens = Vec::new();
     loop {
-        writer.flush()?;
-        let header_inf = main_reader.get_header();
-        if header_inf.is_none() {
+        block.clear();
+        while block.len() < BLOCK_SIZE {
+            let buf = input.fill_buf()?;
+            if buf.is_empty() {
+                break;
+            }
+            let len = std::cmp::min(buf.len(), BLOCK_SIZE - block.len());
+            block.extend_from_slice(&buf[..len]);
+            input.consume(len);
+        }
+        let is_final = input.fill_buf()?.is_empty();
+        debug!("compress block of {} bytes", block.len());
+
+        checksum.update(&block);
+        data_size = data_size.wrapping_add(block.len() as u32);
+        tokens.clear();
+        encoder.encode(&block, &mut tokens);
+        deflate_writer.write_block(&tokens, &block, is_final)?;
+        if is_final {
             break;
         }
-        let header = header_inf.unwrap()?;
-        debug!("read header");
-        match main_reader.parse_header(&header) {
-            Ok((_, mut member_reader)) => {
-                let before_len = writer.byte_count();
-                let mut deflate_reader =
-                    DeflateReader::new(BitReader::new(member_reader.inner_mut()));
-                while let Some(block) = deflate_reader.next_block() {
-                    if let Err(err) = block {
-                        bail!(err)
-                    }
-                    let (block_header, reader) = block?;
-                    match block_header.compression_type {
-                        deflate::CompressionType::Uncompressed => {
-                            debug!("uncompressed");
-                            let reader = reader.borrow_reader_from_boundary();
-                            let (len, nlen) = (
-                                reader.read_u16::<LittleEndian>()?,
-                                reader.read_u16::<LittleEndian>()?,
-                            );
-                            match len != !nlen {
-                                true => {
-                                    bail!("nlen check failed")
-                                }
-                                _ => {
-                                    let mut data = Vec::new();
-                                    data.resize(len as usize, 0_u8);
-                                    reader.read_exact(data.as_mut_slice())?;
-                                    writer.write_all(data.as_slice())?;
-                                }
-                            }
-                        }
-                        deflate::CompressionType::DynamicTree => {
-                            debug!("dynamic");
-                            let (lit_len, distance) = decode_litlen_distance_trees(reader)?;
-                            loop {
-                                match lit_len.read_symbol(reader)? {
-                                    huffman_coding::LitLenToken::Literal(val) => {
-                                        writer.write_all(&[val])?;
-                                    }
-                                    huffman_coding::LitLenToken::EndOfBlock => {
-                                        break;
-                                    }
-                                    huffman_coding::LitLenToken::Length { base, extra_bits } => {
-                                        //  debug!("read len");
-                                        let len = base + reader.read_bits(extra_bits)?.bits();
-                                        //  debug!("read dist token");
-                                        let dist_token = distance.read_symbol(reader)?;
-                                        //debug!("hmm");
-                                        let dist = dist_token.base
-                                            + reader.read_bits(dist_token.extra_bits)?.bits();
-                                        // debug!("before write");
-                                        writer.write_previous(dist as usize, len as usize)?;
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            bail!("unsupported block type")
-                        }
-                    }
-                    if block_header.is_final {
-                        break;
-                    }
-                }
-                let (footer, reader) = member_reader.read_footer()?;
-                if writer.byte_count() - before_len != footer.data_size as usize {
-                    bail!("length check failed");
-                }
-
-                if footer.data_crc32 != writer.crc32() {
-                    bail!("crc32 check failed");
-                }
-                main_reader = reader;
-            }
-            Err(err) => bail!(err),
+    }
+    deflate_writer.finish()?;
+
+    match format {
+        Format::Gzip => {
+            let footer = MemberFooter {
+                data_crc32: checksum.finish(),
+                data_size,
+            };
+            footer.write(&mut output)?;
         }
+        Format::Zlib => zlib::write_trailer(&mut output, checksum.finish())?,
+        Format::Raw => (),
     }
+    output.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let long: Vec<u8> = (0..3 * BLOCK_SIZE)
+            .map(|i| (i % 7 * 31 + i / 1000) as u8)
+            .collect();
+        for data in [&b""[..], b"x", b"abcabcabcabcabc", &long] {
+            for level in 1..=9 {
+                let mut compressed = Vec::new();
+                compress(data, &mut compressed, level)?;
+                let mut decompressed = Vec::new();
+                decompress(compressed.as_slice(), &mut decompressed)?;
+                assert_eq!(decompressed, data, "level {}", level);
+            }
+        }
+        Ok(())
+    }
+}
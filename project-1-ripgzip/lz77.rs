@@ -0,0 +1,246 @@
+#![forbid(unsafe_code)]
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+pub const WINDOW_SIZE: usize = 32768;
+
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const NIL: usize = usize::MAX;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug)]
+pub struct Level {
+    max_chain: usize,
+    nice_len: usize,
+    lazy: bool,
+}
+
+impl Level {
+    /// Search parameters for gzip levels 1 (fastest) to 9 (best ratio).
+    pub fn new(level: u32) -> Self {
+        let (max_chain, nice_len, lazy) = match level {
+            0 | 1 => (4, 8, false),
+            2 => (8, 16, false),
+            3 => (32, 32, false),
+            4 => (16, 16, true),
+            5 => (32, 32, true),
+            6 => (128, 128, true),
+            7 => (256, 128, true),
+            8 => (1024, MAX_MATCH, true),
+            _ => (4096, MAX_MATCH, true),
+        };
+        Self {
+            max_chain,
+            nice_len,
+            lazy,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+pub struct Lz77Encoder {
+    level: Level,
+    window: Vec<u8>,
+    // Absolute stream position of window[0].
+    base: usize,
+    // Absolute position of the next byte that is not yet in the hash chains.
+    // The last bytes of the data are hashed with the data that follows them.
+    hashed: usize,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl Lz77Encoder {
+    pub fn new(level: Level) -> Self {
+        Self {
+            level,
+            window: Vec::new(),
+            base: 0,
+            hashed: 0,
+            head: vec![NIL; HASH_SIZE],
+            prev: vec![NIL; WINDOW_SIZE],
+        }
+    }
+
+    /// Encode `data` into tokens. Matches may refer to the previously encoded
+    /// data, but never run past the end of `data`.
+    pub fn encode(&mut self, data: &[u8], tokens: &mut Vec<Token>) {
+        self.window.extend_from_slice(data);
+        let end = self.base + self.window.len();
+        let mut pos = end - data.len();
+        while pos < end {
+            let (mut len, dist) = self.longest_match(pos, end);
+            if self.level.lazy && len >= MIN_MATCH && len < self.level.nice_len {
+                let (next_len, _) = self.longest_match(pos + 1, end);
+                if next_len > len {
+                    len = 0;
+                }
+            }
+            if len >= MIN_MATCH {
+                tokens.push(Token::Match {
+                    len: len as u16,
+                    dist: dist as u16,
+                });
+                pos += len;
+            } else {
+                tokens.push(Token::Literal(self.byte(pos)));
+                pos += 1;
+            }
+        }
+        self.insert_until(end, end);
+
+        if self.window.len() > 2 * WINDOW_SIZE {
+            let drop = self.window.len() - WINDOW_SIZE;
+            self.window.drain(..drop);
+            self.base += drop;
+        }
+    }
+
+    fn byte(&self, pos: usize) -> u8 {
+        self.window[pos - self.base]
+    }
+
+    fn hash(&self, pos: usize) -> usize {
+        let at = pos - self.base;
+        let value = ((self.window[at] as usize) << 10)
+            ^ ((self.window[at + 1] as usize) << 5)
+            ^ (self.window[at + 2] as usize);
+        value & (HASH_SIZE - 1)
+    }
+
+    fn insert_until(&mut self, pos: usize, end: usize) {
+        while self.hashed < pos && self.hashed + MIN_MATCH <= end {
+            let hash = self.hash(self.hashed);
+            self.prev[self.hashed % WINDOW_SIZE] = self.head[hash];
+            self.head[hash] = self.hashed;
+            self.hashed += 1;
+        }
+    }
+
+    fn longest_match(&mut self, pos: usize, end: usize) -> (usize, usize) {
+        if pos + MIN_MATCH > end {
+            return (0, 0);
+        }
+        self.insert_until(pos, end);
+
+        let max_len = std::cmp::min(MAX_MATCH, end - pos);
+        let current = &self.window[pos - self.base..pos - self.base + max_len];
+        let (mut best_len, mut best_dist) = (MIN_MATCH - 1, 0);
+        let mut candidate = self.head[self.hash(pos)];
+        let mut chain = self.level.max_chain;
+        while candidate != NIL && pos - candidate <= WINDOW_SIZE && chain > 0 {
+            let previous = &self.window[candidate - self.base..];
+            if previous[best_len] == current[best_len] {
+                let len = current
+                    .iter()
+                    .zip(previous)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len >= self.level.nice_len || len == max_len {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[candidate % WINDOW_SIZE];
+            if next == NIL || next >= candidate {
+                break;
+            }
+            candidate = next;
+            chain -= 1;
+        }
+        (best_len, best_dist)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(tokens: &[Token]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => data.push(byte),
+                Token::Match { len, dist } => {
+                    for _ in 0..len {
+                        data.push(data[data.len() - dist as usize]);
+                    }
+                }
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn encode() {
+        let mut encoder = Lz77Encoder::new(Level::new(6));
+        let mut tokens = Vec::new();
+        encoder.encode(b"abcabcabcabcX", &mut tokens);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match { len: 9, dist: 3 },
+                Token::Literal(b'X'),
+            ]
+        );
+
+        encoder.encode(b"abcabc", &mut tokens);
+        assert_eq!(tokens[5..], [Token::Match { len: 6, dist: 7 }]);
+        assert_eq!(expand(&tokens), b"abcabcabcabcXabcabc");
+    }
+
+    #[test]
+    fn match_across_inputs() {
+        let mut encoder = Lz77Encoder::new(Level::new(6));
+        let mut tokens = Vec::new();
+        encoder.encode(b"XYZab", &mut tokens);
+        tokens.clear();
+        // "abcd" starts in the last bytes of the previous input.
+        encoder.encode(b"cdabcd", &mut tokens);
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Literal(b'c'),
+                Token::Literal(b'd'),
+                Token::Match { len: 4, dist: 4 },
+            ]
+        );
+    }
+
+    #[test]
+    fn encode_long_input() {
+        let data: Vec<u8> = (0..200000_u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8 % 7)
+            .collect();
+        for level in 1..=9 {
+            let mut encoder = Lz77Encoder::new(Level::new(level));
+            let mut tokens = Vec::new();
+            for chunk in data.chunks(50000) {
+                encoder.encode(chunk, &mut tokens);
+            }
+            assert!(tokens.len() < data.len());
+            assert_eq!(expand(&tokens), data);
+        }
+    }
+}
@@ -1,11 +1,23 @@
 #![forbid(unsafe_code)]
 
-use std::io::{stdin, stdout};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdin, stdout, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use log::*;
 use structopt::StructOpt;
 
-use ripgzip::decompress;
+use anyhow::{bail, Context};
+use ripgzip::{
+    compress_with_format, compress_with_header, decompress_parallel, decompress_recover,
+    decompress_speculative, BlockEvent, CompressionType, Decoder, Format, Limits, MemberHeader,
+    MemberInfo, Progress,
+};
+
+// Minimum time between two redraws of the progress bar.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+const PROGRESS_BAR_WIDTH: usize = 30;
 
 #[derive(StructOpt, Debug)]
 #[structopt()]
@@ -13,9 +25,676 @@ struct Opts {
     /// Decompress data
     #[structopt(short = "d", long = "decompress")]
     decompress: bool,
+    /// List the members of gzip data: name, comment, mtime, OS, sizes and ratio
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+    /// Test the integrity of compressed data without writing it
+    #[structopt(short = "t", long = "test")]
+    test: bool,
+    /// Print the deflate blocks as JSON lines: their bit offsets, types and code lengths
+    #[structopt(long = "dump-blocks")]
+    dump_blocks: bool,
+    /// Also print the literals and matches of the blocks
+    #[structopt(long = "dump-tokens", requires = "dump-blocks")]
+    dump_tokens: bool,
+    /// Decompress as much as possible of corrupted gzip data, skipping to the
+    /// next valid member after an error
+    #[structopt(long = "recover")]
+    recover: bool,
+    /// Write to standard output, keeping the input files
+    #[structopt(short = "c", long = "stdout")]
+    stdout: bool,
+    /// Keep the input files
+    #[structopt(short = "k", long = "keep")]
+    keep: bool,
+    /// Overwrite existing output files
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// Restore the original file name and modification time when decompressing
+    #[structopt(short = "N", long = "name")]
+    name: bool,
+    /// Do not save the original file name and modification time when compressing
+    #[structopt(short = "n", long = "no-name", conflicts_with = "name")]
+    no_name: bool,
+    /// Compress faster
+    #[structopt(short = "1", long = "fast")]
+    level_1: bool,
+    #[structopt(short = "2", hidden = true)]
+    level_2: bool,
+    #[structopt(short = "3", hidden = true)]
+    level_3: bool,
+    #[structopt(short = "4", hidden = true)]
+    level_4: bool,
+    #[structopt(short = "5", hidden = true)]
+    level_5: bool,
+    #[structopt(short = "6", hidden = true)]
+    level_6: bool,
+    #[structopt(short = "7", hidden = true)]
+    level_7: bool,
+    #[structopt(short = "8", hidden = true)]
+    level_8: bool,
+    /// Compress better
+    #[structopt(short = "9", long = "best")]
+    level_9: bool,
+    /// Container format: gzip, zlib or raw deflate
+    #[structopt(long = "format", default_value = "gzip", possible_values = &["gzip", "zlib", "raw"])]
+    format: Format,
+    /// Preset dictionary for zlib or raw deflate data
+    #[structopt(long = "dictionary", parse(from_os_str))]
+    dictionary: Option<PathBuf>,
+    /// Number of threads for decompressing block-gzipped (BGZF) data, or any
+    /// gzip data with --speculative. Other multi-member data, e.g. from pigz,
+    /// is decoded sequentially from the first member without a BGZF block size
+    #[structopt(short = "p", long = "processes", default_value = "1")]
+    processes: usize,
+    /// Experimental: decompress single-member gzip data with multiple threads
+    /// by guessing deflate block boundaries (reads the whole input into memory)
+    #[structopt(long = "speculative")]
+    speculative: bool,
+    /// Stop decompressing after this many bytes of output
+    #[structopt(long = "max-output")]
+    max_output: Option<u64>,
+    /// Maximum ratio of decompressed to compressed size
+    #[structopt(long = "max-ratio")]
+    max_ratio: Option<u64>,
+    /// Maximum number of gzip members
+    #[structopt(long = "max-members")]
+    max_members: Option<usize>,
+    /// Maximum length of the extra field, file name and comment of gzip headers
+    #[structopt(long = "max-header-field")]
+    max_header_field: Option<usize>,
+    /// Show the progress of decompression on standard error
+    #[structopt(long = "progress")]
+    progress: bool,
+    /// Print the sizes, ratio, block types and throughput after decompressing
+    #[structopt(long = "stats")]
+    stats: bool,
     /// Verbose mode (-v, -vv, -vvv, etc)
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
+    /// Files to process, standard input if none or "-"
+    #[structopt(parse(from_os_str))]
+    files: Vec<PathBuf>,
+}
+
+impl Opts {
+    fn level(&self) -> u32 {
+        let levels = [
+            self.level_1,
+            self.level_2,
+            self.level_3,
+            self.level_4,
+            self.level_5,
+            self.level_6,
+            self.level_7,
+            self.level_8,
+            self.level_9,
+        ];
+        match levels.iter().rposition(|&set| set) {
+            Some(ind) => ind as u32 + 1,
+            None => 6,
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            max_output: self.max_output,
+            max_ratio: self.max_ratio,
+            max_members: self.max_members,
+            max_header_field: self.max_header_field,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self.format {
+            Format::Gzip => ".gz",
+            Format::Zlib => ".zz",
+            Format::Raw => ".deflate",
+        }
+    }
+}
+
+fn megabytes_per_second(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1e6 / elapsed.as_secs_f64().max(1e-9)
+}
+
+/// Redraw the progress line on standard error. The compressed size is
+/// `input_size`, if known.
+fn draw_progress(progress: &Progress, input_size: Option<u64>, elapsed: Duration) {
+    let mut line = String::from("\r");
+    if let Some(size) = input_size.filter(|&size| size > 0) {
+        let fraction = (progress.compressed as f64 / size as f64).min(1.0);
+        let filled = (fraction * PROGRESS_BAR_WIDTH as f64) as usize;
+        line.push_str(&format!(
+            "[{}{}] {:5.1}% ",
+            "#".repeat(filled),
+            " ".repeat(PROGRESS_BAR_WIDTH - filled),
+            100.0 * fraction
+        ));
+    }
+    line.push_str(&format!(
+        "{:.1} MB -> {:.1} MB, {:.1} MB/s, member {}",
+        progress.compressed as f64 / 1e6,
+        progress.uncompressed as f64 / 1e6,
+        megabytes_per_second(progress.uncompressed, elapsed),
+        progress.member
+    ));
+    let mut stderr = io::stderr().lock();
+    let _ = stderr
+        .write_all(line.as_bytes())
+        .and_then(|_| stderr.flush());
+}
+
+fn print_stats(opts: &Opts, progress: &Progress, block_counts: &[u64; 4], elapsed: Duration) {
+    let (compressed, uncompressed) = (progress.compressed, progress.uncompressed);
+    let mut stderr = io::stderr().lock();
+    let _ = writeln!(
+        stderr,
+        "{} -> {} bytes, ratio {:.1}%{}, {:.3} s, {:.1} MB/s",
+        compressed,
+        uncompressed,
+        ratio(compressed, uncompressed),
+        match opts.format {
+            Format::Gzip => format!(
+                ", {} member{}",
+                progress.member,
+                if progress.member == 1 { "" } else { "s" }
+            ),
+            _ => String::new(),
+        },
+        elapsed.as_secs_f64(),
+        megabytes_per_second(uncompressed, elapsed)
+    );
+    let _ = writeln!(
+        stderr,
+        "blocks: {} stored, {} fixed, {} dynamic",
+        block_counts[CompressionType::Uncompressed as usize],
+        block_counts[CompressionType::FixedTree as usize],
+        block_counts[CompressionType::DynamicTree as usize]
+    );
+}
+
+/// Decompress with the sequential decoder, showing the progress and printing
+/// the statistics as requested.
+fn run_decompress_with_stats<R: BufRead, W: Write>(
+    opts: &Opts,
+    mut decoder: Decoder<R>,
+    mut output: W,
+    input_size: Option<u64>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    if opts.progress {
+        let mut last_draw = start;
+        decoder.set_progress(move |progress| {
+            let now = Instant::now();
+            if now - last_draw >= PROGRESS_INTERVAL {
+                last_draw = now;
+                draw_progress(progress, input_size, now - start);
+            }
+        });
+    }
+    decoder.record_blocks(false);
+    let mut block_counts = [0_u64; 4];
+    let mut buf = vec![0_u8; 1 << 16];
+    let result = loop {
+        let result = decoder.read(&mut buf);
+        for event in decoder.take_blocks() {
+            if let BlockEvent::Block(block) = event {
+                block_counts[block.compression_type as usize] += 1;
+            }
+        }
+        match result {
+            Ok(0) => break Ok(()),
+            Ok(len) => output.write_all(&buf[..len])?,
+            Err(err) => break Err(decoder.locate(err)),
+        }
+    };
+    let elapsed = start.elapsed();
+    if opts.progress {
+        draw_progress(&decoder.progress(), input_size, elapsed);
+        eprintln!();
+    }
+    result?;
+    if opts.stats {
+        print_stats(opts, &decoder.progress(), &block_counts, elapsed);
+    }
+    Ok(())
+}
+
+fn run_decompress<R: BufRead, W: Write>(
+    opts: &Opts,
+    input: R,
+    mut output: W,
+    input_size: Option<u64>,
+) -> anyhow::Result<()> {
+    if opts.processes > 1 {
+        // These are only handled by the sequential decoder, and limits also by
+        // the speculative one.
+        let unsupported = [
+            (opts.format != Format::Gzip, "zlib and raw deflate data"),
+            (
+                !opts.speculative && opts.limits() != Limits::default(),
+                "limits",
+            ),
+            (opts.progress, "--progress"),
+            (opts.stats, "--stats"),
+            (opts.dictionary.is_some(), "--dictionary"),
+        ];
+        match unsupported.iter().find(|(is_set, _)| *is_set) {
+            Some((_, what)) => warn!(
+                "--processes is not supported with {}, decompressing sequentially",
+                what
+            ),
+            None => {
+                match opts.speculative {
+                    true => decompress_speculative(input, output, opts.processes, opts.limits())?,
+                    false => decompress_parallel(input, output, opts.processes)?,
+                }
+                return Ok(());
+            }
+        }
+    }
+    let mut decoder = Decoder::new(input, opts.format);
+    decoder.set_limits(opts.limits());
+    if let Some(path) = &opts.dictionary {
+        decoder.set_dictionary(&fs::read(path)?);
+    }
+    if opts.progress || opts.stats {
+        return run_decompress_with_stats(opts, decoder, output, input_size);
+    }
+    io::copy(&mut decoder, &mut output).map_err(|err| decoder.locate(err))?;
+    Ok(())
+}
+
+/// Format a unix timestamp as a UTC date, see http://howardhinnant.github.io/date_algorithms.html.
+fn format_time(timestamp: u32) -> String {
+    if timestamp == 0 {
+        return "-".to_string();
+    }
+    let (days, seconds) = (timestamp as i64 / 86400 + 719468, timestamp % 86400);
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+    match uncompressed {
+        0 => 0.0,
+        _ => 100.0 * (1.0 - compressed as f64 / uncompressed as f64),
+    }
+}
+
+fn print_member(output: &mut impl Write, index: usize, member: &MemberInfo) -> io::Result<()> {
+    let header = &member.header;
+    writeln!(
+        output,
+        "{:>6} {:>12} {:>12} {:>12} {:>6.1}% {:>19} {:<9} {}{}",
+        index,
+        member.offset,
+        member.compressed_size,
+        member.uncompressed_size,
+        ratio(member.compressed_size, member.uncompressed_size),
+        format_time(header.modification_time),
+        header.os_name(),
+        header.name.as_deref().unwrap_or("-"),
+        match &header.comment {
+            Some(comment) => format!(" ({})", comment),
+            None => String::new(),
+        }
+    )
+}
+
+/// Returns whether anything was skipped.
+fn run_recover<R: BufRead, W: Write>(opts: &Opts, input: R, output: W) -> anyhow::Result<bool> {
+    if opts.format != Format::Gzip {
+        bail!("recovery is only supported for gzip data");
+    }
+    let skipped = decompress_recover(input, output)?;
+    for range in &skipped {
+        warn!(
+            "member at {}..{} is damaged, output may be incomplete: {}",
+            range.start, range.end, range.error
+        );
+    }
+    Ok(!skipped.is_empty())
+}
+
+fn run_list<R: BufRead>(opts: &Opts, input: R) -> anyhow::Result<()> {
+    if opts.format != Format::Gzip {
+        bail!("listing is only supported for gzip data");
+    }
+    let mut output = stdout().lock();
+    writeln!(
+        output,
+        "{:>6} {:>12} {:>12} {:>12} {:>7} {:>19} {:<9} name",
+        "member", "offset", "compressed", "uncompressed", "ratio", "mtime", "os"
+    )?;
+
+    let mut decoder = Decoder::new(input, opts.format);
+    decoder.set_limits(opts.limits());
+    decoder.record_members();
+    let (mut count, mut compressed, mut uncompressed) = (0, 0, 0);
+    let mut buf = vec![0_u8; 1 << 16];
+    loop {
+        let len = decoder.read(&mut buf).map_err(|err| decoder.locate(err))?;
+        for member in decoder.take_members() {
+            print_member(&mut output, count, &member)?;
+            count += 1;
+            compressed += member.compressed_size;
+            uncompressed += member.uncompressed_size;
+        }
+        if len == 0 {
+            break;
+        }
+    }
+    if count > 1 {
+        writeln!(
+            output,
+            "{:>6} {:>12} {:>12} {:>12} {:>6.1}%",
+            "total",
+            "",
+            compressed,
+            uncompressed,
+            ratio(compressed, uncompressed)
+        )?;
+    }
+    Ok(())
+}
+
+fn json_list(values: &[u8]) -> String {
+    let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for char in value.chars() {
+        match char {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            char if char.is_control() => result.push_str(&format!("\\u{:04x}", char as u32)),
+            char => result.push(char),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn print_event(output: &mut impl Write, event: &BlockEvent) -> io::Result<()> {
+    match event {
+        BlockEvent::Block(block) => {
+            let block_type = match block.compression_type {
+                CompressionType::Uncompressed => "stored",
+                CompressionType::FixedTree => "fixed",
+                CompressionType::DynamicTree => "dynamic",
+                CompressionType::Reserved => "reserved",
+            };
+            write!(
+                output,
+                "{{\"event\":\"block\",\"member\":{},\"bit_offset\":{},\
+                 \"uncompressed_offset\":{},\"final\":{},\"type\":\"{}\"",
+                block.member,
+                block.bit_offset,
+                block.uncompressed_offset,
+                block.is_final,
+                block_type
+            )?;
+            if let Some(len) = block.stored_len {
+                write!(output, ",\"len\":{}", len)?;
+            }
+            if block.compression_type == CompressionType::DynamicTree {
+                write!(
+                    output,
+                    ",\"litlen_lengths\":{},\"distance_lengths\":{}",
+                    json_list(&block.litlen_lengths),
+                    json_list(&block.distance_lengths)
+                )?;
+            }
+            writeln!(output, "}}")
+        }
+        BlockEvent::Literal(value) => {
+            writeln!(output, "{{\"event\":\"literal\",\"value\":{}}}", value)
+        }
+        BlockEvent::Match { length, distance } => writeln!(
+            output,
+            "{{\"event\":\"match\",\"length\":{},\"distance\":{}}}",
+            length, distance
+        ),
+        BlockEvent::EndOfBlock { bit_offset } => writeln!(
+            output,
+            "{{\"event\":\"end_of_block\",\"bit_offset\":{}}}",
+            bit_offset
+        ),
+    }
+}
+
+/// Print the structure of the deflate data, ending with the error if decoding fails.
+fn run_dump<R: BufRead>(opts: &Opts, input: R) -> anyhow::Result<()> {
+    let mut output = BufWriter::new(stdout().lock());
+    let mut decoder = Decoder::new(input, opts.format);
+    if let Some(path) = &opts.dictionary {
+        decoder.set_dictionary(&fs::read(path)?);
+    }
+    decoder.record_blocks(opts.dump_tokens);
+    let mut buf = vec![0_u8; 1 << 16];
+    loop {
+        let result = decoder.read(&mut buf);
+        for event in decoder.take_blocks() {
+            print_event(&mut output, &event)?;
+        }
+        match result {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(err) => {
+                let err = decoder.locate(err);
+                writeln!(
+                    output,
+                    "{{\"event\":\"error\",\"member\":{},\"offset\":{},\"message\":{}}}",
+                    err.member(),
+                    err.offset(),
+                    json_string(&err.kind().to_string())
+                )?;
+                output.flush()?;
+                return Err(err.into());
+            }
+        }
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Decompress, or just test with `output` being a sink. `input_size` is the
+/// size of the input file, if any. Returns whether there were warnings.
+fn run_decode<R: BufRead, W: Write>(
+    opts: &Opts,
+    input: R,
+    output: W,
+    input_size: Option<u64>,
+) -> anyhow::Result<bool> {
+    match opts.recover {
+        true => run_recover(opts, input, output),
+        false => run_decompress(opts, input, output, input_size).map(|_| false),
+    }
+}
+
+fn process_stdin(opts: &Opts) -> anyhow::Result<bool> {
+    if opts.list {
+        run_list(opts, stdin().lock())?;
+        Ok(false)
+    } else if opts.dump_blocks {
+        run_dump(opts, stdin().lock())?;
+        Ok(false)
+    } else if opts.test {
+        run_decode(opts, stdin().lock(), io::sink(), None)
+    } else if opts.decompress {
+        run_decode(opts, stdin().lock(), stdout().lock(), None)
+    } else {
+        compress_with_format(stdin().lock(), stdout().lock(), opts.level(), opts.format)?;
+        Ok(false)
+    }
+}
+
+fn modification_time(timestamp: u32) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+}
+
+/// Create `path` and fill it with `write`, removing it on failure.
+fn write_file(
+    opts: &Opts,
+    path: &Path,
+    modified: SystemTime,
+    write: impl FnOnce(&mut BufWriter<&File>) -> anyhow::Result<bool>,
+) -> anyhow::Result<bool> {
+    if fs::symlink_metadata(path).is_ok() {
+        if !opts.force {
+            bail!(
+                "{} already exists, use --force to overwrite",
+                path.display()
+            );
+        }
+        fs::remove_file(path).with_context(|| format!("cannot remove {}", path.display()))?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("cannot create {}", path.display()))?;
+    let mut output = BufWriter::new(&file);
+    let result = write(&mut output).and_then(|warnings| {
+        output.flush()?;
+        Ok(warnings)
+    });
+    drop(output);
+    match result {
+        Ok(_) => file.set_modified(modified)?,
+        Err(_) => {
+            let _ = fs::remove_file(path);
+        }
+    }
+    result
+}
+
+fn process_file(opts: &Opts, path: &Path) -> anyhow::Result<bool> {
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        bail!("not a regular file");
+    }
+    let input = || -> anyhow::Result<_> { Ok(BufReader::new(File::open(path)?)) };
+    let input_size = Some(metadata.len());
+    if opts.list {
+        run_list(opts, input()?)?;
+        return Ok(false);
+    }
+    if opts.dump_blocks {
+        run_dump(opts, input()?)?;
+        return Ok(false);
+    }
+    if opts.test {
+        return run_decode(opts, input()?, io::sink(), input_size);
+    }
+    if opts.stdout {
+        return match opts.decompress {
+            true => run_decode(opts, input()?, stdout().lock(), input_size),
+            false => {
+                compress_file(opts, path, &metadata, input()?, stdout().lock())?;
+                Ok(false)
+            }
+        };
+    }
+
+    let file_name = path.file_name().and_then(|name| name.to_str());
+    let warnings = if opts.decompress {
+        let file_name = file_name.context("invalid file name")?;
+        let stem = match (opts.format, file_name.strip_suffix(opts.suffix())) {
+            (_, Some(stem)) if !stem.is_empty() => stem.to_string(),
+            (Format::Gzip, _) if file_name.len() > 4 && file_name.ends_with(".tgz") => {
+                format!("{}.tar", &file_name[..file_name.len() - 4])
+            }
+            _ => bail!("unknown suffix, ignored"),
+        };
+        let mut output_path = path.with_file_name(stem);
+        let mut modified = metadata.modified()?;
+        if opts.name && opts.format == Format::Gzip {
+            let header = MemberHeader::read(input()?)?;
+            if let Some(name) = header.file_name() {
+                output_path = path.with_file_name(name);
+            }
+            if header.modification_time != 0 {
+                modified = modification_time(header.modification_time);
+            }
+        }
+        if output_path == path {
+            bail!("the stored name is the name of the compressed file");
+        }
+        write_file(opts, &output_path, modified, |output| {
+            run_decode(opts, input()?, output, input_size)
+        })?
+    } else {
+        if file_name.is_some_and(|name| name.ends_with(opts.suffix())) && !opts.force {
+            bail!("already has {} suffix, unchanged", opts.suffix());
+        }
+        let mut output_path = path.as_os_str().to_owned();
+        output_path.push(opts.suffix());
+        let output_path = PathBuf::from(output_path);
+        write_file(opts, &output_path, metadata.modified()?, |output| {
+            compress_file(opts, path, &metadata, input()?, output)?;
+            Ok(false)
+        })?
+    };
+
+    // The input may hold data that was skipped, so only remove it when the
+    // output is complete.
+    if warnings {
+        warn!("{}: kept because of the warnings", path.display());
+    } else if !opts.keep {
+        fs::remove_file(path)?;
+    }
+    Ok(warnings)
+}
+
+fn compress_file<R: BufRead, W: Write>(
+    opts: &Opts,
+    path: &Path,
+    metadata: &fs::Metadata,
+    input: R,
+    output: W,
+) -> anyhow::Result<()> {
+    if opts.format != Format::Gzip {
+        return compress_with_format(input, output, opts.level(), opts.format);
+    }
+    let mut header = MemberHeader::with_level(opts.level());
+    if !opts.no_name {
+        header.name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(String::from);
+        header.modification_time = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|time| u32::try_from(time.as_secs()).ok())
+            .unwrap_or(0);
+    }
+    compress_with_header(input, output, opts.level(), &header)
 }
 
 fn main() {
@@ -27,10 +706,33 @@ fn main() {
         .init()
         .expect("failed to initialize logging");
 
-    if opts.decompress {
-        if let Err(err) = decompress(stdin().lock(), stdout().lock()) {
-            error!("{:#}", err);
-            std::process::exit(1);
+    let stdin_path = [PathBuf::from("-")];
+    let files = match opts.files.is_empty() {
+        true => &stdin_path[..],
+        false => &opts.files[..],
+    };
+    let (mut failed, mut warned) = (false, false);
+    for path in files {
+        let result = match path.as_os_str() == "-" {
+            true => process_stdin(&opts),
+            false => process_file(&opts, path),
+        };
+        match result {
+            Ok(warnings) => warned |= warnings,
+            Err(err) if path.as_os_str() == "-" => {
+                error!("{:#}", err);
+                failed = true;
+            }
+            Err(err) => {
+                error!("{}: {:#}", path.display(), err);
+                failed = true;
+            }
         }
     }
+    // Like gzip, exit with 2 if there were only warnings.
+    if failed {
+        std::process::exit(1);
+    } else if warned {
+        std::process::exit(2);
+    }
 }
@@ -0,0 +1,263 @@
+#![forbid(unsafe_code)]
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Cursor, Read, Write};
+use std::panic;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use log::debug;
+
+use crate::error::{Error, ErrorKind};
+use crate::gzip::{bgzf_block_size, MemberFlags};
+use crate::{Decoder, Format};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Members are grouped into jobs of about this many compressed bytes.
+const JOB_SIZE: usize = 1 << 18;
+
+enum ScannedMember {
+    // A whole member, whose size is known from its BGZF block size.
+    Block(Vec<u8>),
+    // A member of unknown size, only the consumed beginning of it is returned.
+    Unsized(Vec<u8>),
+    End,
+}
+
+fn scan_member<R: BufRead>(input: &mut R) -> io::Result<ScannedMember> {
+    if input.fill_buf()?.is_empty() {
+        return Ok(ScannedMember::End);
+    }
+    let mut data = vec![0_u8; 10];
+    input.read_exact(&mut data)?;
+    if !MemberFlags::from(data[3]).has_extra() {
+        return Ok(ScannedMember::Unsized(data));
+    }
+
+    let mut extra_len = [0_u8; 2];
+    input.read_exact(&mut extra_len)?;
+    data.extend_from_slice(&extra_len);
+    let mut extra = vec![0_u8; u16::from_le_bytes(extra_len) as usize];
+    input.read_exact(&mut extra)?;
+    data.extend_from_slice(&extra);
+
+    match bgzf_block_size(&extra) {
+        Some(block_size) if block_size as usize + 1 >= data.len() => {
+            let header_len = data.len();
+            data.resize(block_size as usize + 1, 0);
+            input.read_exact(&mut data[header_len..])?;
+            Ok(ScannedMember::Block(data))
+        }
+        _ => Ok(ScannedMember::Unsized(data)),
+    }
+}
+
+// Members of the input decoded by one thread.
+struct Job {
+    index: usize,
+    // Index and offset of the first member, for error reporting.
+    member: usize,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+// A panic is reported as a failure of the job rather than losing its result.
+fn run_job(job: &Job) -> Result<Vec<u8>, Error> {
+    panic::catch_unwind(|| decode_job(job))
+        .unwrap_or_else(|_| Err(thread_failed(job.member, job.offset)))
+}
+
+fn decode_job(job: &Job) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+    let mut decoder = Decoder::new(job.data.as_slice(), Format::Gzip);
+    match decoder.read_to_end(&mut output) {
+        Ok(_) => Ok(output),
+        Err(err) => Err(decoder.locate(err).relocate(job.member, job.offset)),
+    }
+}
+
+fn thread_failed(member: usize, offset: u64) -> Error {
+    let err = io::Error::other("decoding thread failed");
+    Error::new(ErrorKind::Io(err), member, offset)
+}
+
+/// Decompress gzip `input` using `threads` worker threads. Members with a BGZF
+/// block size are decoded in parallel, the rest of the input is decoded
+/// sequentially starting from the first member without one.
+pub fn decompress_parallel<R: BufRead, W: Write>(
+    mut input: R,
+    mut output: W,
+    threads: usize,
+) -> Result<(), Error> {
+    let (job_sender, job_receiver) = mpsc::channel::<Job>();
+    let (result_sender, result_receiver) = mpsc::channel::<(Job, Result<Vec<u8>, Error>)>();
+    let job_receiver = Mutex::new(job_receiver);
+    let threads = threads.max(1);
+    let max_jobs_in_flight = 2 * threads;
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (job_receiver, result_sender) = (&job_receiver, result_sender.clone());
+            scope.spawn(move || loop {
+                let job = job_receiver.lock().unwrap().recv();
+                match job {
+                    Ok(job) => {
+                        let result = run_job(&job);
+                        if result_sender.send((job, result)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(_) => return,
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut sent = 0;
+        let mut written = 0;
+        let mut finished = BTreeMap::new();
+        // Index and offset of the first member of each job sent.
+        let mut positions = Vec::new();
+        let mut write_next = |finished: &mut BTreeMap<usize, (Job, Result<Vec<u8>, Error>)>,
+                              written: &mut usize,
+                              positions: &[(usize, u64)]|
+         -> Result<(), Error> {
+            let (job, result) = result_receiver.recv().map_err(|_| {
+                let (member, offset) = positions[*written];
+                thread_failed(member, offset)
+            })?;
+            finished.insert(job.index, (job, result));
+            while let Some((job, result)) = finished.remove(written) {
+                output
+                    .write_all(&result?)
+                    .map_err(|err| Error::from_io(err, job.member, job.offset))?;
+                *written += 1;
+            }
+            Ok(())
+        };
+
+        // Position of the next member to scan and of the beginning of the current job.
+        let (mut member, mut offset) = (0, 0_u64);
+        let mut job = Job {
+            index: 0,
+            member: 0,
+            offset: 0,
+            data: Vec::new(),
+        };
+        let rest = loop {
+            let scanned =
+                scan_member(&mut input).map_err(|err| Error::from_io(err, member, offset))?;
+            let rest = match scanned {
+                ScannedMember::Block(data) => {
+                    job.data.extend_from_slice(&data);
+                    member += 1;
+                    offset += data.len() as u64;
+                    None
+                }
+                ScannedMember::Unsized(data) => Some(data),
+                ScannedMember::End => Some(Vec::new()),
+            };
+            if !job.data.is_empty() && (job.data.len() >= JOB_SIZE || rest.is_some()) {
+                debug!("job {} of {} bytes", sent, job.data.len());
+                positions.push((job.member, job.offset));
+                let next_job = Job {
+                    index: sent + 1,
+                    member,
+                    offset,
+                    data: Vec::new(),
+                };
+                job_sender
+                    .send(std::mem::replace(&mut job, next_job))
+                    .map_err(|_| thread_failed(member, offset))?;
+                sent += 1;
+            }
+            while sent - written >= max_jobs_in_flight {
+                write_next(&mut finished, &mut written, &positions)?;
+            }
+            if let Some(rest) = rest {
+                break rest;
+            }
+        };
+        drop(job_sender);
+        while written < sent {
+            write_next(&mut finished, &mut written, &positions)?;
+        }
+
+        if !rest.is_empty() {
+            debug!("decoding the rest sequentially");
+            let mut decoder = Decoder::new(Cursor::new(rest).chain(&mut input), Format::Gzip);
+            io::copy(&mut decoder, &mut output)
+                .map_err(|err| decoder.locate(err).relocate(member, offset))?;
+        }
+        output
+            .flush()
+            .map_err(|err| Error::from_io(err, member, offset))?;
+        Ok(())
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{Checksum, Crc32};
+    use crate::{compress, compress_with_format};
+    use anyhow::Result;
+
+    fn bgzf_block(data: &[u8]) -> Result<Vec<u8>> {
+        let mut deflated = Vec::new();
+        compress_with_format(data, &mut deflated, 6, Format::Raw)?;
+        let block_size = (18 + deflated.len() + 8 - 1) as u16;
+
+        let mut block = vec![
+            0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+        ];
+        block.extend_from_slice(&block_size.to_le_bytes());
+        block.extend_from_slice(&deflated);
+        let mut checksum = Crc32::new();
+        checksum.update(data);
+        block.extend_from_slice(&checksum.finish().to_le_bytes());
+        block.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        Ok(block)
+    }
+
+    #[test]
+    fn bgzf_blocks() -> Result<()> {
+        let data: Vec<u8> = (0..3000000_u32)
+            .map(|i| (i / 1000 % 7 + i % 3) as u8)
+            .collect();
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(65280) {
+            compressed.extend(bgzf_block(chunk)?);
+        }
+        compressed.extend(bgzf_block(&[])?);
+        // A trailing member without a block size is decoded sequentially.
+        compress(&data[..100], &mut compressed, 6)?;
+
+        let mut output = Vec::new();
+        decompress_parallel(compressed.as_slice(), &mut output, 4)?;
+        assert_eq!(output[..data.len()], data);
+        assert_eq!(output[data.len()..], data[..100]);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_block() -> Result<()> {
+        let data = vec![42_u8; 200000];
+        let mut compressed = Vec::new();
+        for chunk in data.chunks(65280) {
+            compressed.extend(bgzf_block(chunk)?);
+        }
+        let len = compressed.len();
+        compressed[len - 5] ^= 1;
+
+        let mut output = Vec::new();
+        let err = decompress_parallel(compressed.as_slice(), &mut output, 2).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::CrcMismatch { .. }));
+        assert_eq!(err.member(), 3);
+        assert_eq!(err.offset(), len as u64);
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+#![forbid(unsafe_code)]
+
+use std::io::{self, BufRead, Read, Write};
+
+use log::debug;
+
+use crate::error::Error;
+use crate::gzip::{is_plausible_header, GzipReader};
+use crate::{Decoder, Format};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const BUF_SIZE: usize = 1 << 16;
+// Candidate headers with longer names or extra fields are ignored.
+const MAX_HEADER_LEN: usize = 1 << 17;
+// At most this many bytes of a member are kept to search it for other members
+// after an error.
+const MAX_REWIND: u64 = 1 << 20;
+
+/// Reader which can look ahead more than the inner `fill_buf` returns, and
+/// go back to a marked position.
+struct Lookahead<R> {
+    inner: R,
+    // The bytes taken from `inner`: already read ones before `pos` (kept only
+    // after the mark) and unread ones after it.
+    buf: Vec<u8>,
+    pos: usize,
+    position: u64,
+    mark: Option<u64>,
+}
+
+impl<R: BufRead> Lookahead<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            position: 0,
+            mark: None,
+        }
+    }
+
+    /// Keep the data from `mark`, which must not be after the current position,
+    /// for `rewind`. Ignored if that data was already dropped.
+    fn set_mark(&mut self, mark: u64) {
+        let buf_start = self.position - self.pos as u64;
+        self.mark = (mark >= buf_start).then_some(mark);
+    }
+
+    /// Go back to `position` if it is not before the mark.
+    fn rewind(&mut self, position: u64) -> bool {
+        match self.mark.take() {
+            Some(mark) if mark <= position && position <= self.position => {
+                self.pos -= (self.position - position) as usize;
+                self.position = position;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Return at least `len` next bytes, or less at the end of the input.
+    fn peek(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.buf.len() - self.pos < len {
+            let drained = match self.mark {
+                Some(mark) => self.pos - (self.position - mark) as usize,
+                None => self.pos,
+            };
+            self.buf.drain(..drained);
+            self.pos -= drained;
+            while self.buf.len() < len {
+                let data = self.inner.fill_buf()?;
+                if data.is_empty() {
+                    break;
+                }
+                let data_len = data.len();
+                self.buf.extend_from_slice(data);
+                self.inner.consume(data_len);
+            }
+        }
+        Ok(&self.buf[self.pos..])
+    }
+}
+
+impl<R: BufRead> Read for Lookahead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.read(buf)?;
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<R: BufRead> BufRead for Lookahead<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match self.pos < self.buf.len() {
+            true => Ok(&self.buf[self.pos..]),
+            false => self.inner.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        if self.pos < self.buf.len() {
+            self.pos += amt;
+        } else if self.mark.is_some() {
+            // The data is already buffered by the inner reader.
+            if let Ok(data) = self.inner.fill_buf() {
+                self.buf.extend_from_slice(&data[..amt]);
+                self.pos += amt;
+            }
+            self.inner.consume(amt);
+        } else {
+            self.inner.consume(amt);
+        }
+
+        if let Some(mark) = self.mark {
+            if self.position - mark > MAX_REWIND {
+                self.mark = None;
+            }
+        }
+        if self.mark.is_none() && self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        }
+    }
+}
+
+fn is_valid_header(data: &[u8]) -> bool {
+    if !is_plausible_header(data) {
+        return false;
+    }
+    let mut gzip_reader = GzipReader::new(data);
+    match gzip_reader.get_header() {
+        Some(Ok(header)) => gzip_reader.parse_header(&header).is_ok(),
+        _ => false,
+    }
+}
+
+/// Skip to the next valid member header. Returns false at the end of the input.
+fn find_member<R: BufRead>(input: &mut Lookahead<R>) -> io::Result<bool> {
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        match buf.iter().position(|&byte| byte == 0x1f) {
+            Some(pos) => input.consume(pos),
+            None => {
+                let len = buf.len();
+                input.consume(len);
+                continue;
+            }
+        }
+        if is_plausible_header(input.peek(10)?) && is_valid_header(input.peek(MAX_HEADER_LEN)?) {
+            return Ok(true);
+        }
+        input.consume(1);
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Part of the compressed input that was dropped to recover from an error: from
+/// the start of the broken member to the next valid member. The data decoded
+/// from the broken member before the error is still written out.
+#[derive(Debug)]
+pub struct SkippedRange {
+    pub start: u64,
+    pub end: u64,
+    pub error: Error,
+}
+
+/// Decompress gzip `input`, salvaging as much as possible: on a decoding error,
+/// write out the data decoded before it and continue from the next valid member
+/// header, searching from the start of the broken member if it is not too long.
+/// Only errors writing the `output` are returned.
+pub fn decompress_recover<R: BufRead, W: Write>(
+    input: R,
+    mut output: W,
+) -> Result<Vec<SkippedRange>, Error> {
+    let mut input = Lookahead::new(input);
+    let mut skipped = Vec::new();
+    let mut buf = vec![0_u8; BUF_SIZE];
+    loop {
+        let start = input.position;
+        input.set_mark(start);
+        let mut decoder = Decoder::new(&mut input, Format::Gzip);
+        decoder.record_members();
+        let mut member_start = start;
+        let error = loop {
+            let result = decoder.read(&mut buf);
+            if let Some(member) = decoder.take_members().last() {
+                member_start = start + member.offset + member.compressed_size;
+                decoder.get_mut().set_mark(member_start);
+            }
+            match result {
+                Ok(0) => {
+                    output.flush().map_err(|err| decoder.locate(err))?;
+                    return Ok(skipped);
+                }
+                Ok(len) => output
+                    .write_all(&buf[..len])
+                    .map_err(|err| decoder.locate(err))?,
+                Err(err) => break decoder.locate(err).relocate(0, start),
+            }
+        };
+        // Flush the data decoded before the error.
+        while let Ok(len @ 1..) = decoder.read(&mut buf) {
+            output
+                .write_all(&buf[..len])
+                .map_err(|err| decoder.locate(err))?;
+        }
+        drop(decoder);
+
+        debug!("{}", error);
+        // Another member may start inside the broken one, e.g. after a truncated member.
+        input.rewind(member_start + 1);
+        let found =
+            find_member(&mut input).map_err(|err| Error::from_io(err, 0, input.position))?;
+        debug!("skipped bytes {}..{}", member_start, input.position);
+        skipped.push(SkippedRange {
+            start: member_start,
+            end: input.position,
+            error,
+        });
+        if !found {
+            output
+                .flush()
+                .map_err(|err| Error::from_io(err, 0, input.position))?;
+            return Ok(skipped);
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress;
+    use crate::error::ErrorKind;
+    use crate::test_util::XorShift;
+    use anyhow::Result;
+
+    #[test]
+    fn corrupted_member() -> Result<()> {
+        let data: Vec<u8> = XorShift::new(1)
+            .take(300000)
+            .map(|state| (state % 16) as u8)
+            .collect();
+        let mut compressed = Vec::new();
+        compress(&data[..100000], &mut compressed, 6)?;
+        let second = compressed.len();
+        compress(&data[100000..200000], &mut compressed, 6)?;
+        let third = compressed.len();
+        compress(&data[200000..], &mut compressed, 6)?;
+        for byte in &mut compressed[second + 2000..second + 2100] {
+            *byte = 0x1f;
+        }
+
+        let mut output = Vec::new();
+        let skipped = decompress_recover(compressed.as_slice(), &mut output)?;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].start, second as u64);
+        assert_eq!(skipped[0].end, third as u64);
+        assert_eq!(output[..100000], data[..100000]);
+        assert_eq!(output[output.len() - 100000..], data[200000..]);
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_input() -> Result<()> {
+        let data = b"recover me, recover me, recover me".repeat(1000);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 1)?;
+        compressed.truncate(compressed.len() - 20);
+
+        let mut output = Vec::new();
+        let skipped = decompress_recover(compressed.as_slice(), &mut output)?;
+        assert_eq!(skipped.len(), 1);
+        assert!(matches!(skipped[0].error.kind(), ErrorKind::TruncatedInput));
+        assert_eq!(skipped[0].end, compressed.len() as u64);
+        assert!(!output.is_empty());
+        assert_eq!(output, data[..output.len()]);
+        Ok(())
+    }
+
+    #[test]
+    fn member_after_truncated_one() -> Result<()> {
+        let data = b"truncated member".repeat(1000);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compressed.truncate(compressed.len() / 2);
+        let second = compressed.len();
+        compress(&b"second member"[..], &mut compressed, 6)?;
+
+        let mut output = Vec::new();
+        let skipped = decompress_recover(compressed.as_slice(), &mut output)?;
+        assert_eq!(skipped.len(), 1);
+        assert_eq!((skipped[0].start, skipped[0].end), (0, second as u64));
+        assert!(output.ends_with(b"second member"));
+        Ok(())
+    }
+}
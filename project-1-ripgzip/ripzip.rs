@@ -0,0 +1,236 @@
+#![forbid(unsafe_code)]
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, stdout, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use structopt::StructOpt;
+
+use anyhow::{bail, Context};
+use ripgzip::{ZipArchive, ZipEntry, ZipMethod};
+
+#[derive(StructOpt, Debug)]
+#[structopt()]
+struct Opts {
+    /// List the entries: sizes, ratio, modification time, CRC32 and name
+    #[structopt(short = "l", long = "list")]
+    list: bool,
+    /// Test the integrity of the entries without writing them
+    #[structopt(short = "t", long = "test")]
+    test: bool,
+    /// Write the entries to standard output
+    #[structopt(short = "p", long = "pipe")]
+    pipe: bool,
+    /// Directory to extract the entries into
+    #[structopt(
+        short = "d",
+        long = "directory",
+        default_value = ".",
+        parse(from_os_str)
+    )]
+    directory: PathBuf,
+    /// Overwrite existing files
+    #[structopt(short = "f", long = "force")]
+    force: bool,
+    /// Verbose mode (-v, -vv, -vvv, etc)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+    /// Zip archive
+    #[structopt(parse(from_os_str))]
+    archive: PathBuf,
+    /// Names of the entries to process, all of them if none
+    names: Vec<String>,
+}
+
+fn ratio(compressed: u64, uncompressed: u64) -> f64 {
+    match uncompressed {
+        0 => 0.0,
+        _ => 100.0 * (1.0 - compressed as f64 / uncompressed as f64),
+    }
+}
+
+fn print_entry(output: &mut impl Write, entry: &ZipEntry) -> io::Result<()> {
+    writeln!(
+        output,
+        "{:>12} {:>12} {:>6.1}% {:<9} {:>19} {:08x} {}",
+        entry.uncompressed_size,
+        entry.compressed_size,
+        ratio(entry.compressed_size, entry.uncompressed_size),
+        match entry.method {
+            ZipMethod::Stored => "stored".to_string(),
+            ZipMethod::Deflate => "deflate".to_string(),
+            ZipMethod::Deflate64 => "deflate64".to_string(),
+            ZipMethod::Unknown(method) => method.to_string(),
+        },
+        entry.modified,
+        entry.crc32,
+        entry.name
+    )
+}
+
+fn run_list<R: io::Read + io::Seek>(
+    archive: &ZipArchive<R>,
+    indices: &[usize],
+) -> anyhow::Result<()> {
+    let mut output = stdout().lock();
+    writeln!(
+        output,
+        "{:>12} {:>12} {:>7} {:<9} {:>19} {:>8} name",
+        "uncompressed", "compressed", "ratio", "method", "mtime", "crc32"
+    )?;
+    let (mut compressed, mut uncompressed) = (0, 0);
+    for &index in indices {
+        let entry = &archive.entries()[index];
+        print_entry(&mut output, entry)?;
+        compressed += entry.compressed_size;
+        uncompressed += entry.uncompressed_size;
+    }
+    if indices.len() > 1 {
+        writeln!(
+            output,
+            "{:>12} {:>12} {:>6.1}% {:<9} {:>19} {:>8} {} entries",
+            uncompressed,
+            compressed,
+            ratio(compressed, uncompressed),
+            "",
+            "",
+            "",
+            indices.len()
+        )?;
+    }
+    Ok(())
+}
+
+/// Create `path` and fill it with the data of the entry, removing it on failure.
+fn extract_file<R: io::Read + io::Seek>(
+    opts: &Opts,
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    path: &Path,
+) -> anyhow::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        if !opts.force {
+            bail!(
+                "{} already exists, use --force to overwrite",
+                path.display()
+            );
+        }
+        fs::remove_file(path).with_context(|| format!("cannot remove {}", path.display()))?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("cannot create {}", parent.display()))?;
+    }
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .with_context(|| format!("cannot create {}", path.display()))?;
+    let mut output = BufWriter::new(&file);
+    let result = archive.open(index).and_then(|mut reader| {
+        io::copy(&mut reader, &mut output)?;
+        output.flush()?;
+        Ok(())
+    });
+    drop(output);
+    if result.is_err() {
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// Extract, test or print one entry. Returns whether it was skipped.
+fn process_entry<R: io::Read + io::Seek>(
+    opts: &Opts,
+    archive: &mut ZipArchive<R>,
+    index: usize,
+) -> anyhow::Result<bool> {
+    let entry = archive.entries()[index].clone();
+    if opts.test || opts.pipe {
+        if !entry.is_dir() {
+            let mut reader = archive.open(index)?;
+            match opts.pipe {
+                true => io::copy(&mut reader, &mut stdout().lock())?,
+                false => io::copy(&mut reader, &mut io::sink())?,
+            };
+        }
+        return Ok(false);
+    }
+
+    let path = match entry.enclosed_path() {
+        Some(path) => opts.directory.join(path),
+        None => {
+            warn!(
+                "{}: path outside of the target directory, skipped",
+                entry.name
+            );
+            return Ok(true);
+        }
+    };
+    info!("extracting {}", path.display());
+    match entry.is_dir() {
+        true => fs::create_dir_all(&path)
+            .with_context(|| format!("cannot create {}", path.display()))?,
+        false => extract_file(opts, archive, index, &path)?,
+    }
+    Ok(false)
+}
+
+/// Returns whether there were warnings, failing if there were errors.
+fn run(opts: &Opts) -> anyhow::Result<bool> {
+    let file = File::open(&opts.archive)
+        .with_context(|| format!("cannot open {}", opts.archive.display()))?;
+    let mut archive = ZipArchive::new(BufReader::new(file))?;
+    let indices = match opts.names.is_empty() {
+        true => (0..archive.entries().len()).collect(),
+        false => opts
+            .names
+            .iter()
+            .map(|name| {
+                archive
+                    .index_of(name)
+                    .with_context(|| format!("{}: not found in the archive", name))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+    if opts.list {
+        run_list(&archive, &indices)?;
+        return Ok(false);
+    }
+
+    let (mut failed, mut warned) = (false, false);
+    for index in indices {
+        match process_entry(opts, &mut archive, index) {
+            Ok(skipped) => warned |= skipped,
+            Err(err) => {
+                error!("{}: {:#}", archive.entries()[index].name, err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        bail!("some entries failed");
+    }
+    Ok(warned)
+}
+
+fn main() {
+    let opts = Opts::from_args();
+
+    stderrlog::new()
+        .verbosity(1 + opts.verbose)
+        .timestamp(stderrlog::Timestamp::Off)
+        .init()
+        .expect("failed to initialize logging");
+
+    // Like ripgzip, exit with 2 if there were only warnings.
+    match run(&opts) {
+        Ok(false) => (),
+        Ok(true) => std::process::exit(2),
+        Err(err) => {
+            error!("{}: {:#}", opts.archive.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,604 @@
+#![forbid(unsafe_code)]
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
+use log::debug;
+
+use crate::bit_reader::BitReader;
+use crate::checksum::{Checksum, Crc32};
+use crate::decoder::MIN_RATIO_CHECK_OUTPUT;
+use crate::deflate::{BlockDecoder, Deflate};
+use crate::error::{Error, ErrorKind, Limit};
+use crate::gzip::{GzipReader, MemberFooter};
+use crate::huffman_coding::{read_code_lengths, LitLenToken};
+use crate::{Decoder, Format, Limits};
+
+////////////////////////////////////////////////////////////////////////////////
+
+// Compressed bytes per chunk.
+const CHUNK_SIZE: usize = 1 << 20;
+// Decoded bytes per compressed byte of a chunk, beyond which the member is
+// decoded sequentially, as the symbols of a chunk are kept in memory.
+const MAX_CHUNK_RATIO: usize = 8;
+const WINDOW_SIZE: usize = 32768;
+// Decoded symbols are bytes or markers for the byte at `index` of the unknown
+// window preceding the chunk, stored as `MARKER + index`.
+const MARKER: u16 = 256;
+
+/// Deflate blocks decoded from a bit position, possibly without the window.
+struct Chunk {
+    start_bit: u64,
+    // The first block boundary at or after the end of the chunk, or the end of
+    // the final block.
+    end_bit: u64,
+    is_final: bool,
+    symbols: Vec<u16>,
+}
+
+fn bit_position(reader: &BitReader<&[u8]>, data: &[u8]) -> u64 {
+    (data.len() - reader.get_ref().len()) as u64 * 8 - reader.buffered_bits() as u64
+}
+
+/// Decode the blocks of `data` from `start_bit` until a block boundary at or
+/// after `stop_bit`, or until the final block. Back-references before the
+/// start become markers if the `window` is unknown. Returns `None` once more
+/// than `max_len` symbols are decoded.
+fn decode_blocks(
+    data: &[u8],
+    start_bit: u64,
+    stop_bit: u64,
+    window: Option<&[u8]>,
+    max_len: usize,
+) -> Result<Option<Chunk>> {
+    let mut symbols: Vec<u16> = match window {
+        Some(window) => window.iter().map(|&byte| byte as u16).collect(),
+        None => (0..WINDOW_SIZE as u16)
+            .map(|index| MARKER + index)
+            .collect(),
+    };
+    let prefix_len = symbols.len();
+    let mut reader = BitReader::new(&data[(start_bit / 8) as usize..]);
+    reader.read_bits((start_bit % 8) as u8)?;
+
+    loop {
+        if symbols.len() - prefix_len > max_len {
+            return Ok(None);
+        }
+        let position = bit_position(&reader, data);
+        if position >= stop_bit && position > start_bit {
+            return Ok(Some(Chunk {
+                start_bit,
+                end_bit: position,
+                is_final: false,
+                symbols: symbols.split_off(prefix_len),
+            }));
+        }
+        let is_final = reader.read_bits(1)?.bits() == 1;
+        let (lit_len, distance) = match reader.read_bits(2)?.bits() {
+            0 => {
+                let stream = reader.borrow_reader_from_boundary();
+                let (len, nlen) = (
+                    stream.read_u16::<LittleEndian>()?,
+                    stream.read_u16::<LittleEndian>()?,
+                );
+                if len != !nlen {
+                    bail!(ErrorKind::StoredLengthMismatch);
+                }
+                if stream.len() < len as usize {
+                    bail!(ErrorKind::TruncatedInput);
+                }
+                symbols.extend(stream[..len as usize].iter().map(|&byte| byte as u16));
+                *stream = &stream[len as usize..];
+                if is_final {
+                    break;
+                }
+                continue;
+            }
+            1 => Deflate.fixed_trees()?,
+            2 => {
+                let (litlen_lengths, distance_lengths) =
+                    read_code_lengths(&mut reader, Deflate.distance_codes())?;
+                Deflate.trees(&litlen_lengths, &distance_lengths)?
+            }
+            _ => bail!(ErrorKind::InvalidBlockType),
+        };
+        loop {
+            if symbols.len() - prefix_len > max_len {
+                return Ok(None);
+            }
+            match lit_len.read_symbol(&mut reader)? {
+                LitLenToken::Literal(byte) => symbols.push(byte as u16),
+                LitLenToken::EndOfBlock => break,
+                LitLenToken::Length { base, extra_bits } => {
+                    let len = base + reader.read_bits(extra_bits)?.bits();
+                    let token = distance.read_symbol(&mut reader)?;
+                    let dist = (token.base + reader.read_bits(token.extra_bits)?.bits()) as usize;
+                    if dist > symbols.len() {
+                        bail!(ErrorKind::DistanceTooFar {
+                            distance: dist,
+                            available: symbols.len(),
+                        });
+                    }
+                    for _ in 0..len {
+                        symbols.push(symbols[symbols.len() - dist]);
+                    }
+                }
+            }
+        }
+        if is_final {
+            break;
+        }
+    }
+    Ok(Some(Chunk {
+        start_bit,
+        end_bit: bit_position(&reader, data),
+        is_final: true,
+        symbols: symbols.split_off(prefix_len),
+    }))
+}
+
+/// Cheap check of a candidate non-final stored or dynamic block header: stored
+/// lengths must match, dynamic code lengths must be valid.
+fn is_block_start(data: &[u8], bit: u64) -> bool {
+    let mut reader = BitReader::new(&data[(bit / 8) as usize..]);
+    let header = reader
+        .read_bits((bit % 8) as u8)
+        .and_then(|_| reader.read_bits(3));
+    match header.map(|header| header.bits()) {
+        Ok(0b000) => {
+            let stream = reader.borrow_reader_from_boundary();
+            matches!(
+                (stream.read_u16::<LittleEndian>(), stream.read_u16::<LittleEndian>()),
+                (Ok(len), Ok(nlen)) if len == !nlen
+            )
+        }
+        Ok(0b100) => read_code_lengths(&mut reader, Deflate.distance_codes()).is_ok(),
+        _ => false,
+    }
+}
+
+/// Guess the first block boundary in `from_bit..to_bit` by trying to decode
+/// non-final dynamic and stored blocks at every position.
+fn find_chunk(data: &[u8], from_bit: u64, to_bit: u64, max_len: usize) -> Option<Chunk> {
+    for bit in from_bit..to_bit {
+        let byte = data[(bit / 8) as usize] as u16;
+        let next = data.get((bit / 8) as usize + 1).copied().unwrap_or(0) as u16;
+        let header = ((byte | next << 8) >> (bit % 8)) & 0b111;
+        // Not final, stored or dynamic.
+        if header != 0b000 && header != 0b100 {
+            continue;
+        }
+        if !is_block_start(data, bit) {
+            continue;
+        }
+        match decode_blocks(data, bit, to_bit, None, max_len) {
+            Ok(Some(chunk)) => {
+                debug!("guessed block boundary at bit {}", bit);
+                return Some(chunk);
+            }
+            // Likely the boundary, but its chunk is too large to be kept.
+            Ok(None) => return None,
+            Err(_) => (),
+        }
+    }
+    None
+}
+
+/// Replace the markers of a chunk using the `window` preceding it.
+fn resolve(symbols: &[u16], window: &[u8]) -> Result<Vec<u8>> {
+    symbols
+        .iter()
+        .map(|&symbol| match symbol.checked_sub(MARKER) {
+            None => Ok(symbol as u8),
+            Some(index) => {
+                let distance = WINDOW_SIZE - index as usize;
+                match window.len().checked_sub(distance) {
+                    Some(position) => Ok(window[position]),
+                    None => bail!(ErrorKind::DistanceTooFar {
+                        distance,
+                        available: window.len(),
+                    }),
+                }
+            }
+        })
+        .collect()
+}
+
+/// Read the header of the first member, within the `limits`.
+fn read_header(data: &mut &[u8], limits: &Limits) -> Result<()> {
+    let mut gzip_reader = GzipReader::new(data);
+    if let Some(max_len) = limits.max_header_field {
+        gzip_reader.set_max_field_len(max_len);
+    }
+    let header = match gzip_reader.get_header() {
+        Some(header) => header?,
+        None => bail!(ErrorKind::TruncatedInput),
+    };
+    if limits.max_members == Some(0) {
+        bail!(ErrorKind::LimitExceeded(Limit::Members(0)));
+    }
+    gzip_reader.parse_header(&header)?;
+    Ok(())
+}
+
+/// The limit exceeded by `output_size` bytes decoded from `input_size` bytes.
+fn exceeded_limit(limits: &Limits, input_size: u64, output_size: u64) -> Option<Limit> {
+    if let Some(max_output) = limits.max_output {
+        if output_size > max_output {
+            return Some(Limit::OutputSize(max_output));
+        }
+    }
+    if let Some(max_ratio) = limits.max_ratio {
+        if output_size > MIN_RATIO_CHECK_OUTPUT && output_size / input_size.max(1) > max_ratio {
+            return Some(Limit::Ratio(max_ratio));
+        }
+    }
+    None
+}
+
+fn thread_failed(offset: u64) -> Error {
+    let err = io::Error::other("decoding thread failed");
+    Error::new(ErrorKind::Io(err), 0, offset)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Experimental: decompress a gzip member with `threads` worker threads, like
+/// rapidgzip. The compressed data is split into chunks, and each chunk is decoded
+/// from a guessed block boundary, before the data preceding it is known. Chunks
+/// whose guess turns out wrong are decoded again sequentially. The whole `input`
+/// is read into memory; members after the first one are decoded sequentially,
+/// and so is the first one if it is too compressible for the chunks to be kept
+/// in memory.
+pub fn decompress_speculative<R: Read, W: Write>(
+    input: R,
+    output: W,
+    threads: usize,
+    limits: Limits,
+) -> Result<(), Error> {
+    decompress_chunks(input, output, threads, CHUNK_SIZE, limits)?;
+    Ok(())
+}
+
+/// Returns the number of chunks decoded from a guessed block boundary.
+fn decompress_chunks<R: Read, W: Write>(
+    mut input: R,
+    mut output: W,
+    threads: usize,
+    chunk_size: usize,
+    limits: Limits,
+) -> Result<usize, Error> {
+    let mut compressed = Vec::new();
+    input
+        .read_to_end(&mut compressed)
+        .map_err(|err| Error::from_io(err, 0, 0))?;
+    // Like an input without members for the other decoders.
+    if compressed.is_empty() {
+        return Ok(0);
+    }
+    let mut rest = compressed.as_slice();
+    read_header(&mut rest, &limits).map_err(|err| Error::from_anyhow(err, 0, 0))?;
+    let header_len = compressed.len() - rest.len();
+    let data = &compressed[header_len..];
+    let locate =
+        |err: anyhow::Error, bit: u64| Error::from_anyhow(err, 0, header_len as u64 + bit / 8);
+    let max_len = MAX_CHUNK_RATIO * chunk_size;
+
+    let chunk_count = data.len().div_ceil(chunk_size).max(1);
+    let chunk_range = |index: usize| {
+        let end = ((index + 1) * chunk_size).min(data.len());
+        ((index * chunk_size) as u64 * 8, end as u64 * 8)
+    };
+    let (job_sender, job_receiver) = mpsc::channel::<usize>();
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, Option<Chunk>)>();
+    let job_receiver = Mutex::new(job_receiver);
+    let threads = threads.max(1);
+    let max_jobs_in_flight = 2 * threads;
+
+    let mut written = 0_u64;
+    let mut guessed = 0;
+    let member = thread::scope(|scope| {
+        for _ in 0..threads {
+            let (job_receiver, result_sender) = (&job_receiver, result_sender.clone());
+            scope.spawn(move || loop {
+                let index = match job_receiver.lock().unwrap().recv() {
+                    Ok(index) => index,
+                    Err(_) => return,
+                };
+                let (from_bit, to_bit) = chunk_range(index);
+                let chunk = match index {
+                    0 => decode_blocks(data, 0, to_bit, Some(&[]), max_len)
+                        .ok()
+                        .flatten(),
+                    _ => find_chunk(data, from_bit, to_bit, max_len),
+                };
+                if result_sender.send((index, chunk)).is_err() {
+                    return;
+                }
+            });
+        }
+        drop(result_sender);
+        // Dropped on return, which stops the workers.
+        let job_sender = job_sender;
+
+        let mut sent = 0;
+        let mut finished = BTreeMap::new();
+        let mut window = Vec::new();
+        let mut checksum = Crc32::new();
+        let mut size = 0_u32;
+        let mut position = 0;
+        for index in 0..chunk_count {
+            while sent < chunk_count && sent < index + max_jobs_in_flight {
+                job_sender
+                    .send(sent)
+                    .map_err(|_| thread_failed(header_len as u64))?;
+                sent += 1;
+            }
+            let chunk = loop {
+                if let Some(chunk) = finished.remove(&index) {
+                    break chunk;
+                }
+                let (index, chunk) = result_receiver
+                    .recv()
+                    .map_err(|_| thread_failed(header_len as u64))?;
+                finished.insert(index, chunk);
+            };
+
+            let (_, to_bit) = chunk_range(index);
+            if position >= to_bit {
+                // Already decoded along with the previous chunk.
+                continue;
+            }
+            let (chunk, mut decoded) = match chunk {
+                Some(chunk) if chunk.start_bit == position => {
+                    let decoded = resolve(&chunk.symbols, &window)
+                        .map_err(|err| locate(err, chunk.start_bit))?;
+                    if index > 0 {
+                        guessed += 1;
+                    }
+                    (chunk, decoded)
+                }
+                _ => {
+                    debug!(
+                        "chunk {}: decoding sequentially from bit {}",
+                        index, position
+                    );
+                    let chunk = decode_blocks(data, position, to_bit, Some(&window), max_len)
+                        .map_err(|err| locate(err, position))?;
+                    let chunk = match chunk {
+                        Some(chunk) => chunk,
+                        None => return Ok(None),
+                    };
+                    let decoded = chunk.symbols.iter().map(|&symbol| symbol as u8).collect();
+                    (chunk, decoded)
+                }
+            };
+            written += decoded.len() as u64;
+            let input_size = header_len as u64 + chunk.end_bit / 8;
+            let exceeded = exceeded_limit(&limits, input_size, written);
+            if let Some(Limit::OutputSize(max_output)) = exceeded {
+                // Keep the output up to the limit, like `Decoder`.
+                decoded.truncate(decoded.len() - (written - max_output) as usize);
+            }
+            output
+                .write_all(&decoded)
+                .map_err(|err| locate(err.into(), chunk.end_bit))?;
+            if let Some(limit) = exceeded {
+                return Err(Error::new(ErrorKind::LimitExceeded(limit), 0, input_size));
+            }
+            checksum.update(&decoded);
+            size = size.wrapping_add(decoded.len() as u32);
+            window.extend_from_slice(&decoded);
+            window.drain(..window.len().saturating_sub(WINDOW_SIZE));
+            position = chunk.end_bit;
+            if chunk.is_final {
+                return Ok(Some((position, checksum.finish(), size)));
+            }
+        }
+        Err(Error::new(
+            ErrorKind::TruncatedInput,
+            0,
+            (header_len + data.len()) as u64,
+        ))
+    })?;
+
+    let (end_bit, checksum, size) = match member {
+        Some(member) => member,
+        None => {
+            debug!("decoding sequentially from the start, skipping the output written");
+            let mut decoder = Decoder::new(compressed.as_slice(), Format::Gzip);
+            decoder.set_limits(limits);
+            let skipped = io::copy(&mut (&mut decoder).take(written), &mut io::sink());
+            skipped.map_err(|err| decoder.locate(err))?;
+            io::copy(&mut decoder, &mut output).map_err(|err| decoder.locate(err))?;
+            output.flush().map_err(|err| decoder.locate(err))?;
+            return Ok(guessed);
+        }
+    };
+
+    let mut rest = &data[end_bit.div_ceil(8) as usize..];
+    let footer_offset = (header_len + data.len() - rest.len()) as u64;
+    let footer =
+        MemberFooter::read(&mut rest).map_err(|err| Error::from_anyhow(err, 0, footer_offset))?;
+    let offset = footer_offset + 8;
+    if footer.data_size != size {
+        let kind = ErrorKind::LengthMismatch {
+            expected: footer.data_size,
+            actual: size,
+        };
+        return Err(Error::new(kind, 0, offset));
+    }
+    if footer.data_crc32 != checksum {
+        let kind = ErrorKind::CrcMismatch {
+            expected: footer.data_crc32,
+            actual: checksum,
+        };
+        return Err(Error::new(kind, 0, offset));
+    }
+
+    if !rest.is_empty() {
+        debug!("decoding the other members sequentially");
+        let mut decoder = Decoder::new(rest, Format::Gzip);
+        decoder.set_limits(limits);
+        decoder.set_preceding(1, offset, written);
+        io::copy(&mut decoder, &mut output).map_err(|err| decoder.locate(err))?;
+    }
+    output
+        .flush()
+        .map_err(|err| Error::from_io(err, 0, offset))?;
+    Ok(guessed)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress;
+    use crate::test_util::XorShift;
+
+    fn test_data(len: usize) -> Vec<u8> {
+        let words = [
+            &b"speculative "[..],
+            b"block ",
+            b"boundary ",
+            b"marker ",
+            b"\n",
+        ];
+        let mut data = Vec::new();
+        let mut states = XorShift::new(1);
+        while data.len() < len {
+            let state = states.next().unwrap();
+            match state % 8 {
+                0..=4 => data.extend_from_slice(words[(state >> 8) as usize % words.len()]),
+                _ => data.push((state >> 16) as u8),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn speculative() -> Result<()> {
+        let data = test_data(1 << 20);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compress(&data[..1000], &mut compressed, 1)?;
+
+        let mut output = Vec::new();
+        let guessed = decompress_chunks(
+            compressed.as_slice(),
+            &mut output,
+            4,
+            1 << 15,
+            Limits::default(),
+        )?;
+        assert!(guessed > 0);
+        assert_eq!(output.len(), data.len() + 1000);
+        assert!(output[..data.len()] == data);
+        assert_eq!(output[data.len()..], data[..1000]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_input() -> Result<()> {
+        let mut output = Vec::new();
+        decompress_speculative(&[][..], &mut output, 4, Limits::default())?;
+        assert!(output.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted() -> Result<()> {
+        let data = test_data(1 << 18);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        let crc_pos = compressed.len() - 8;
+        compressed[crc_pos] ^= 1;
+
+        let limits = Limits::default();
+        let err =
+            decompress_chunks(compressed.as_slice(), io::sink(), 2, 1 << 15, limits).unwrap_err();
+        assert!(matches!(err.kind(), ErrorKind::CrcMismatch { .. }));
+        assert_eq!(err.offset(), compressed.len() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn compressible() -> Result<()> {
+        let mut data = test_data(1 << 16);
+        data.resize(1 << 22, 0);
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compress(&data[..1000], &mut compressed, 1)?;
+
+        // The chunks of zeros are too large, so the member is decoded again.
+        let mut output = Vec::new();
+        let limits = Limits::default();
+        decompress_chunks(compressed.as_slice(), &mut output, 4, 1 << 12, limits)?;
+        assert_eq!(output.len(), data.len() + 1000);
+        assert!(output[..data.len()] == data);
+        assert_eq!(output[data.len()..], data[..1000]);
+        Ok(())
+    }
+
+    #[test]
+    fn limits() -> Result<()> {
+        let data = test_data((1 << 20) + (1 << 16));
+        let mut compressed = Vec::new();
+        compress(data.as_slice(), &mut compressed, 6)?;
+        compress(&data[..1000], &mut compressed, 1)?;
+        let decompress = |limits| {
+            let mut output = Vec::new();
+            let result = decompress_chunks(compressed.as_slice(), &mut output, 2, 1 << 15, limits);
+            (output, result.unwrap_err())
+        };
+
+        let (output, err) = decompress(Limits {
+            max_output: Some(100000),
+            ..Limits::default()
+        });
+        assert!(output == data[..100000]);
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::OutputSize(100000))
+        ));
+
+        let max_output = data.len() as u64 + 10;
+        let (output, err) = decompress(Limits {
+            max_output: Some(max_output),
+            ..Limits::default()
+        });
+        assert_eq!(output.len() as u64, max_output);
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::OutputSize(size)) if *size == max_output
+        ));
+        assert_eq!(err.member(), 1);
+
+        let (output, err) = decompress(Limits {
+            max_members: Some(1),
+            ..Limits::default()
+        });
+        assert_eq!(output.len(), data.len());
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::Members(1))
+        ));
+        assert_eq!(err.member(), 1);
+
+        let (_, err) = decompress(Limits {
+            max_ratio: Some(1),
+            ..Limits::default()
+        });
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::LimitExceeded(Limit::Ratio(1))
+        ));
+        Ok(())
+    }
+}
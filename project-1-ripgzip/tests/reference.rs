@@ -0,0 +1,106 @@
+#![forbid(unsafe_code)]
+
+use std::io::{Read, Write};
+
+use flate2::{
+    read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder},
+    write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+    Compression,
+};
+use proptest::{collection::vec, prelude::*};
+use ripgzip::{compress_with_format, decompress_with_format, Format};
+
+////////////////////////////////////////////////////////////////////////////////
+
+fn reference_compress(data: &[u8], level: u32, format: Format) -> Vec<u8> {
+    let level = Compression::new(level);
+    let result = match format {
+        Format::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), level);
+            encoder.write_all(data).and_then(|_| encoder.finish())
+        }
+        Format::Zlib => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), level);
+            encoder.write_all(data).and_then(|_| encoder.finish())
+        }
+        Format::Raw => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), level);
+            encoder.write_all(data).and_then(|_| encoder.finish())
+        }
+    };
+    result.unwrap()
+}
+
+fn reference_decompress(data: &[u8], format: Format) -> std::io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    match format {
+        Format::Gzip => MultiGzDecoder::new(data).read_to_end(&mut output),
+        Format::Zlib => ZlibDecoder::new(data).read_to_end(&mut output),
+        Format::Raw => DeflateDecoder::new(data).read_to_end(&mut output),
+    }?;
+    Ok(output)
+}
+
+fn decompress(data: &[u8], format: Format) -> Result<Vec<u8>, ripgzip::Error> {
+    let mut output = Vec::new();
+    decompress_with_format(data, &mut output, format)?;
+    Ok(output)
+}
+
+fn format() -> impl Strategy<Value = Format> {
+    prop_oneof![Just(Format::Gzip), Just(Format::Zlib), Just(Format::Raw)]
+}
+
+/// Random bytes compress badly, so also generate runs and small alphabets
+/// to get matches and dynamic blocks with skewed codes.
+fn input() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        vec(any::<u8>(), 0..2000),
+        vec((any::<u8>(), 1..300_usize), 0..100).prop_map(|runs| {
+            runs.into_iter()
+                .flat_map(|(byte, len)| std::iter::repeat_n(byte, len))
+                .collect()
+        }),
+        vec(0..4_u8, 0..50000),
+    ]
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+proptest! {
+    #[test]
+    fn decompress_reference(data in input(), level in 0..=9_u32, format in format()) {
+        let compressed = reference_compress(&data, level, format);
+        prop_assert_eq!(decompress(&compressed, format)?, data);
+    }
+
+    #[test]
+    fn compress_for_reference(data in input(), level in 1..=9_u32, format in format()) {
+        let mut compressed = Vec::new();
+        compress_with_format(data.as_slice(), &mut compressed, level, format).unwrap();
+        prop_assert_eq!(reference_decompress(&compressed, format)?, data);
+    }
+
+    #[test]
+    fn corrupted(
+        data in input(),
+        format in format(),
+        changes in vec((any::<prop::sample::Index>(), any::<u8>()), 1..4),
+        truncate in any::<Option<prop::sample::Index>>(),
+    ) {
+        let mut compressed = reference_compress(&data, 6, format);
+        for (index, byte) in changes {
+            let index = index.index(compressed.len());
+            compressed[index] ^= byte;
+        }
+        if let Some(index) = truncate {
+            compressed.truncate(index.index(compressed.len()));
+        }
+        // Either decoder may reject data the other accepts, but they must
+        // agree on the output when both succeed.
+        let result = decompress(&compressed, format);
+        if let (Ok(output), Ok(expected)) = (result, reference_decompress(&compressed, format)) {
+            prop_assert_eq!(output, expected);
+        }
+    }
+}
@@ -1,98 +1,166 @@
 #![forbid(unsafe_code)]
-use std::collections::VecDeque;
 use std::io::{self, Write};
 
 use anyhow::{bail, Result};
-use crc::{Crc, Digest, CRC_32_ISO_HDLC};
+
+use crate::checksum::{Checksum, Crc32};
+use crate::error::ErrorKind;
 
 ////////////////////////////////////////////////////////////////////////////////
 
-const HISTORY_SIZE: usize = 32768;
-static CRC_CHECKER: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
-pub struct TrackingWriter<'a, T> {
+/// History size of deflate, Deflate64 doubles it.
+pub const HISTORY_SIZE: usize = 32768;
+
+pub struct TrackingWriter<T> {
     inner: T,
     len: usize,
-    hist: VecDeque<u8>,
-    digest: Option<Digest<'a, u32>>,
+    // How far back `write_previous` can refer.
+    history_size: usize,
+    // Ring buffer of the last written bytes. Twice the history, so that
+    // checksum updates can lag behind and be done in large batches.
+    window: Box<[u8]>,
+    // Position in `window` where the next byte goes.
+    window_end: usize,
+    // Number of valid bytes in `window`.
+    window_len: usize,
+    // Number of bytes before `window_end` not yet added to the checksum.
+    unchecked: usize,
+    // Buffer for the output of `write_previous`.
+    scratch: Vec<u8>,
+    checksum: Box<dyn Checksum>,
 }
 
-impl<T: Write> Write for TrackingWriter<'_, T> {
+impl<T: Write> Write for TrackingWriter<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        match self.inner.write(buf) {
-            Ok(written) => {
-                self.digest.as_mut().unwrap().update(&buf[..written]);
-                self.len += written;
-                if self.hist.len() + std::cmp::min(written, HISTORY_SIZE) > HISTORY_SIZE {
-                    self.hist.drain(
-                        ..std::cmp::min(
-                            self.hist.len(),
-                            self.hist.len() + std::cmp::min(written, HISTORY_SIZE) - HISTORY_SIZE,
-                        ),
-                    );
-                }
-                let start = match written > HISTORY_SIZE {
-                    true => written - HISTORY_SIZE,
-                    _ => 0,
-                };
-                self.hist.extend(buf[start..].iter());
-                Ok(written)
-            }
-            Err(err) => Err(err),
+        let written = self.inner.write(buf)?;
+        let data = &buf[..written];
+        self.len += written;
+        if self.unchecked + written > self.window.len() {
+            self.update_checksum();
         }
+        match written > self.window.len() {
+            true => self.checksum.update(data),
+            false => self.unchecked += written,
+        }
+        self.push_window(data);
+        Ok(written)
     }
 
     fn flush(&mut self) -> io::Result<()> {
         self.len = 0;
-        self.hist.clear();
-        self.digest = Some(CRC_CHECKER.digest());
+        self.window_end = 0;
+        self.window_len = 0;
+        self.unchecked = 0;
+        self.checksum.reset();
         self.inner.flush()
     }
 }
 
-impl<T: Write> TrackingWriter<'_, T> {
+impl<T: Write> TrackingWriter<T> {
+    #[allow(unused)]
     pub fn new(inner: T) -> Self {
-        let mut hist = VecDeque::new();
-        hist.reserve(HISTORY_SIZE);
+        Self::with_checksum(inner, Box::new(Crc32::new()), HISTORY_SIZE)
+    }
+
+    pub fn with_checksum(inner: T, checksum: Box<dyn Checksum>, history_size: usize) -> Self {
         Self {
             inner,
             len: 0,
-            hist,
-            digest: Some(CRC_CHECKER.digest()),
+            history_size,
+            window: vec![0; 2 * history_size].into_boxed_slice(),
+            window_end: 0,
+            window_len: 0,
+            unchecked: 0,
+            scratch: Vec::new(),
+            checksum,
         }
     }
 
+    fn push_window(&mut self, data: &[u8]) {
+        let size = self.window.len();
+        let data = &data[data.len().saturating_sub(size)..];
+        let head = data.len().min(size - self.window_end);
+        self.window[self.window_end..self.window_end + head].copy_from_slice(&data[..head]);
+        self.window[..data.len() - head].copy_from_slice(&data[head..]);
+        self.window_end = (self.window_end + data.len()) % size;
+        self.window_len = (self.window_len + data.len()).min(size);
+    }
+
+    /// Copy `len` bytes of the window starting `dist` bytes before its end.
+    fn copy_window(&self, dist: usize, len: usize, output: &mut Vec<u8>) {
+        let size = self.window.len();
+        let start = (self.window_end + size - dist) % size;
+        let head = len.min(size - start);
+        output.extend_from_slice(&self.window[start..start + head]);
+        output.extend_from_slice(&self.window[..len - head]);
+    }
+
+    fn update_checksum(&mut self) {
+        let size = self.window.len();
+        let start = (self.window_end + size - self.unchecked) % size;
+        let head = self.unchecked.min(size - start);
+        self.checksum.update(&self.window[start..start + head]);
+        self.checksum.update(&self.window[..self.unchecked - head]);
+        self.unchecked = 0;
+    }
+
+    /// The last written bytes that `write_previous` can refer to.
+    pub fn history(&self) -> Vec<u8> {
+        let len = self.window_len.min(self.history_size);
+        let mut history = Vec::with_capacity(len);
+        self.copy_window(len, len, &mut history);
+        history
+    }
+
+    /// Make `data` available to `write_previous` without writing it, e.g. for
+    /// a preset dictionary.
+    pub fn preload_history(&mut self, data: &[u8]) {
+        self.update_checksum();
+        self.push_window(&data[data.len().saturating_sub(self.history_size)..]);
+    }
+
     /// Write a sequence of `len` bytes written `dist` bytes ago.
     pub fn write_previous(&mut self, dist: usize, len: usize) -> Result<()> {
-        if self.len < dist || dist > HISTORY_SIZE {
-            bail!("distance is bigger than length of written")
+        let available = self.window_len.min(self.history_size);
+        if available < dist {
+            bail!(ErrorKind::DistanceTooFar {
+                distance: dist,
+                available,
+            })
         }
 
-        let begin = std::cmp::min(HISTORY_SIZE, self.len) - dist;
-        self.hist.make_contiguous();
-        let data = self.hist.as_slices().0[begin..begin + dist].to_vec();
-        let mut bytes_written = 0;
-        while bytes_written < len {
-            match self.write(&data.as_slice()[0..std::cmp::min(dist, len - bytes_written)]) {
-                Ok(written) => {
-                    if written < std::cmp::min(dist, len - bytes_written) {
-                        bail!("bad writing!")
-                    } else {
-                        bytes_written += written;
-                    }
-                }
-                Err(err) => panic!("{}", err),
+        let mut data = std::mem::take(&mut self.scratch);
+        data.clear();
+        if dist == 1 {
+            let size = self.window.len();
+            data.resize(len, self.window[(self.window_end + size - 1) % size]);
+        } else {
+            self.copy_window(dist, dist.min(len), &mut data);
+            // The match overlaps its own output: repeat the period, doubling it.
+            while data.len() < len {
+                data.extend_from_within(..data.len().min(len - data.len()));
             }
         }
+        let result = self.write(&data);
+        self.scratch = data;
+        if result? < len {
+            bail!("bad writing!")
+        }
         Ok(())
     }
 
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
     pub fn byte_count(&self) -> usize {
         self.len
     }
 
-    pub fn crc32(&mut self) -> u32 {
-        let dig = self.digest.take().unwrap();
-        dig.finalize()
+    /// The checksum of the bytes written since the last flush. Starts over.
+    pub fn checksum(&mut self) -> u32 {
+        self.update_checksum();
+        self.checksum.finish()
     }
 }
 
@@ -101,6 +169,7 @@ impl<T: Write> TrackingWriter<'_, T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::XorShift;
     use byteorder::WriteBytesExt;
 
     #[test]
@@ -119,7 +188,7 @@ mod tests {
 
         assert_eq!(writer.write(&[42, 124, 234, 27])?, 0);
         assert_eq!(writer.byte_count(), 10);
-        assert_eq!(writer.crc32(), 2992191065);
+        assert_eq!(writer.checksum(), 2992191065);
 
         Ok(())
     }
@@ -144,8 +213,47 @@ mod tests {
 
         assert!(writer.write_previous(1, 1).is_err());
         assert_eq!(writer.byte_count(), 512);
-        assert_eq!(writer.crc32(), 2733545866);
+        assert_eq!(writer.checksum(), 2733545866);
 
         Ok(())
     }
+
+    #[test]
+    fn window_wraparound() -> Result<()> {
+        for history_size in [HISTORY_SIZE, 2 * HISTORY_SIZE] {
+            check_window_wraparound(history_size)?;
+        }
+        Ok(())
+    }
+
+    fn check_window_wraparound(history_size: usize) -> Result<()> {
+        let mut writer =
+            TrackingWriter::with_checksum(Vec::new(), Box::new(Crc32::new()), history_size);
+        let mut expected: Vec<u8> = Vec::new();
+        for (round, state) in XorShift::new(7).take(2000).enumerate() {
+            if round % 3 == 0 || expected.is_empty() {
+                let len = match round % 100 {
+                    0 => 70000,
+                    _ => state as usize % 300,
+                };
+                let data: Vec<u8> = (0..len).map(|i| (i as u32 ^ state) as u8).collect();
+                writer.write_all(&data)?;
+                expected.extend_from_slice(&data);
+            } else {
+                let dist = 1 + (state >> 8) as usize % expected.len().min(history_size);
+                let len = 3 + state as usize % 256;
+                writer.write_previous(dist, len)?;
+                for _ in 0..len {
+                    expected.push(expected[expected.len() - dist]);
+                }
+            }
+        }
+        assert_eq!(writer.byte_count(), expected.len());
+        assert_eq!(writer.history(), expected[expected.len() - history_size..]);
+        let mut checksum = Crc32::new();
+        checksum.update(&expected);
+        assert_eq!(writer.checksum(), checksum.finish());
+        assert_eq!(*writer.inner_mut(), expected);
+        Ok(())
+    }
 }
@@ -0,0 +1,598 @@
+#![forbid(unsafe_code)]
+
+use std::fmt;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{bail, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::checksum::{Checksum, Crc32};
+use crate::deflate::{BlockDecoder, Deflate, Deflate64};
+use crate::error::ErrorKind;
+use crate::{Decoder, Format};
+
+////////////////////////////////////////////////////////////////////////////////
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const LOCAL_HEADER_LEN: usize = 30;
+const END_LEN: u64 = 22;
+const ZIP64_LOCATOR_LEN: u64 = 20;
+const MAX_COMMENT_LEN: u64 = 65535;
+
+const FLAG_ENCRYPTED: u16 = 1;
+const FLAG_UTF8: u16 = 1 << 11;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZipMethod {
+    Stored,
+    Deflate,
+    /// Enhanced deflate, see `Deflate64`.
+    Deflate64,
+    Unknown(u16),
+}
+
+impl From<u16> for ZipMethod {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => Self::Stored,
+            8 => Self::Deflate,
+            9 => Self::Deflate64,
+            method => Self::Unknown(method),
+        }
+    }
+}
+
+/// MS-DOS date and time of the last modification, in local time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DosDateTime {
+    pub date: u16,
+    pub time: u16,
+}
+
+impl fmt::Display for DosDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            1980 + (self.date >> 9),
+            (self.date >> 5) & 0xf,
+            self.date & 0x1f,
+            self.time >> 11,
+            (self.time >> 5) & 0x3f,
+            (self.time & 0x1f) * 2
+        )
+    }
+}
+
+/// An entry of the central directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZipEntry {
+    /// Names not flagged as UTF-8 are in code page 437, which is only decoded
+    /// correctly for ASCII.
+    pub name: String,
+    pub method: ZipMethod,
+    pub modified: DosDateTime,
+    pub crc32: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub is_encrypted: bool,
+    /// Offset of the local header in the archive.
+    pub header_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// Relative path to extract the entry to, or None if the name is absolute
+    /// or goes up with "..", so that it could escape the target directory.
+    pub fn enclosed_path(&self) -> Option<PathBuf> {
+        let path = Path::new(&self.name);
+        let mut result = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(name) => result.push(name),
+                Component::CurDir => (),
+                _ => return None,
+            }
+        }
+        Some(result)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Location of the central directory, from the end of central directory record.
+struct CentralDirectory {
+    entry_count: u64,
+    size: u64,
+    offset: u64,
+}
+
+/// Find the end of central directory record among the last bytes of the archive.
+fn read_end<R: Read + Seek>(reader: &mut R) -> Result<CentralDirectory> {
+    let archive_len = reader.seek(SeekFrom::End(0))?;
+    let tail_len = archive_len.min(END_LEN + MAX_COMMENT_LEN);
+    reader.seek(SeekFrom::Start(archive_len - tail_len))?;
+    let mut tail = vec![0_u8; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+
+    // The comment may contain the signature as well, so take the last record
+    // whose comment fits in the archive.
+    let position = (0..tail.len().saturating_sub(END_LEN as usize - 1))
+        .rev()
+        .find(|&pos| {
+            let record = &tail[pos..];
+            (&record[..4]).read_u32::<LittleEndian>().ok() == Some(END_SIGNATURE)
+                && record.len() as u64
+                    >= END_LEN + u16::from_le_bytes([record[20], record[21]]) as u64
+        });
+    let position = match position {
+        Some(position) => position,
+        None => bail!("end of central directory not found, not a zip archive"),
+    };
+    let mut record = &tail[position + 4..];
+    let disk = record.read_u16::<LittleEndian>()?;
+    let directory_disk = record.read_u16::<LittleEndian>()?;
+    let _disk_entry_count = record.read_u16::<LittleEndian>()?;
+    let entry_count = record.read_u16::<LittleEndian>()?;
+    let size = record.read_u32::<LittleEndian>()?;
+    let offset = record.read_u32::<LittleEndian>()?;
+
+    if entry_count != u16::MAX && size != u32::MAX && offset != u32::MAX {
+        if disk != 0 || directory_disk != 0 {
+            bail!("multi-disk archives are not supported");
+        }
+        return Ok(CentralDirectory {
+            entry_count: entry_count as u64,
+            size: size as u64,
+            offset: offset as u64,
+        });
+    }
+    let end_offset = archive_len - tail_len + position as u64;
+    read_zip64_end(reader, end_offset)
+}
+
+/// Read the ZIP64 end of central directory record through the locator, which
+/// precedes the end of central directory record.
+fn read_zip64_end<R: Read + Seek>(reader: &mut R, end_offset: u64) -> Result<CentralDirectory> {
+    if end_offset < ZIP64_LOCATOR_LEN {
+        bail!("ZIP64 end of central directory locator not found");
+    }
+    reader.seek(SeekFrom::Start(end_offset - ZIP64_LOCATOR_LEN))?;
+    if reader.read_u32::<LittleEndian>()? != ZIP64_LOCATOR_SIGNATURE {
+        bail!("ZIP64 end of central directory locator not found");
+    }
+    let _disk = reader.read_u32::<LittleEndian>()?;
+    let record_offset = reader.read_u64::<LittleEndian>()?;
+    if reader.read_u32::<LittleEndian>()? > 1 {
+        bail!("multi-disk archives are not supported");
+    }
+
+    reader.seek(SeekFrom::Start(record_offset))?;
+    if reader.read_u32::<LittleEndian>()? != ZIP64_END_SIGNATURE {
+        bail!("invalid ZIP64 end of central directory signature");
+    }
+    let _record_size = reader.read_u64::<LittleEndian>()?;
+    let _version_made_by = reader.read_u16::<LittleEndian>()?;
+    let _version_needed = reader.read_u16::<LittleEndian>()?;
+    let disk = reader.read_u32::<LittleEndian>()?;
+    let directory_disk = reader.read_u32::<LittleEndian>()?;
+    if disk != 0 || directory_disk != 0 {
+        bail!("multi-disk archives are not supported");
+    }
+    let _disk_entry_count = reader.read_u64::<LittleEndian>()?;
+    Ok(CentralDirectory {
+        entry_count: reader.read_u64::<LittleEndian>()?,
+        size: reader.read_u64::<LittleEndian>()?,
+        offset: reader.read_u64::<LittleEndian>()?,
+    })
+}
+
+/// Parse a central directory header, advancing `record` past it.
+fn parse_entry(record: &mut &[u8]) -> Result<ZipEntry> {
+    if record.read_u32::<LittleEndian>()? != CENTRAL_HEADER_SIGNATURE {
+        bail!("invalid central directory header signature");
+    }
+    let _version_made_by = record.read_u16::<LittleEndian>()?;
+    let _version_needed = record.read_u16::<LittleEndian>()?;
+    let flags = record.read_u16::<LittleEndian>()?;
+    let method = ZipMethod::from(record.read_u16::<LittleEndian>()?);
+    let time = record.read_u16::<LittleEndian>()?;
+    let date = record.read_u16::<LittleEndian>()?;
+    let crc32 = record.read_u32::<LittleEndian>()?;
+    let mut compressed_size = record.read_u32::<LittleEndian>()? as u64;
+    let mut uncompressed_size = record.read_u32::<LittleEndian>()? as u64;
+    let name_len = record.read_u16::<LittleEndian>()? as usize;
+    let extra_len = record.read_u16::<LittleEndian>()? as usize;
+    let comment_len = record.read_u16::<LittleEndian>()? as usize;
+    let _disk = record.read_u16::<LittleEndian>()?;
+    let _internal_attributes = record.read_u16::<LittleEndian>()?;
+    let _external_attributes = record.read_u32::<LittleEndian>()?;
+    let mut header_offset = record.read_u32::<LittleEndian>()? as u64;
+
+    if record.len() < name_len + extra_len + comment_len {
+        bail!("truncated central directory");
+    }
+    let name = &record[..name_len];
+    let name = match flags & FLAG_UTF8 {
+        0 => String::from_utf8_lossy(name).into_owned(),
+        _ => match std::str::from_utf8(name) {
+            Ok(name) => name.to_string(),
+            Err(_) => bail!("invalid UTF-8 entry name"),
+        },
+    };
+
+    // The ZIP64 extra field holds the fields set to u32::MAX, in this order.
+    let mut extra = &record[name_len..name_len + extra_len];
+    while extra.len() >= 4 {
+        let id = extra.read_u16::<LittleEndian>()?;
+        let len = (extra.read_u16::<LittleEndian>()? as usize).min(extra.len());
+        let (mut data, rest) = extra.split_at(len);
+        extra = rest;
+        if id != ZIP64_EXTRA_ID {
+            continue;
+        }
+        for field in [
+            &mut uncompressed_size,
+            &mut compressed_size,
+            &mut header_offset,
+        ] {
+            if *field == u32::MAX as u64 {
+                *field = match data.read_u64::<LittleEndian>() {
+                    Ok(value) => value,
+                    Err(_) => bail!("{}: truncated ZIP64 extra field", name),
+                };
+            }
+        }
+    }
+    *record = &record[name_len + extra_len + comment_len..];
+
+    Ok(ZipEntry {
+        name,
+        method,
+        modified: DosDateTime { date, time },
+        crc32,
+        compressed_size,
+        uncompressed_size,
+        is_encrypted: flags & FLAG_ENCRYPTED != 0,
+        header_offset,
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A zip archive, read through its central directory.
+pub struct ZipArchive<R> {
+    reader: R,
+    entries: Vec<ZipEntry>,
+}
+
+impl<R: Read + Seek> ZipArchive<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let directory = read_end(&mut reader)?;
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        if directory.offset.saturating_add(directory.size) > archive_len {
+            bail!("central directory beyond the end of the archive");
+        }
+        reader.seek(SeekFrom::Start(directory.offset))?;
+        let mut data = vec![0_u8; directory.size as usize];
+        reader.read_exact(&mut data)?;
+
+        let mut record = data.as_slice();
+        let mut entries = Vec::new();
+        while (entries.len() as u64) < directory.entry_count {
+            entries.push(parse_entry(&mut record)?);
+        }
+        Ok(Self { reader, entries })
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Index of the entry with the given name.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.name == name)
+    }
+
+    /// Stream the decompressed data of an entry. Its size and CRC32 are checked
+    /// when the end of the data is read.
+    pub fn open(&mut self, index: usize) -> Result<ZipEntryReader<'_, R>> {
+        let entry = match self.entries.get(index) {
+            Some(entry) => entry,
+            None => bail!("no entry {} in the archive", index),
+        };
+        if entry.is_encrypted {
+            bail!("encrypted entries are not supported");
+        }
+        if let ZipMethod::Unknown(method) = entry.method {
+            bail!("unsupported compression method {}", method);
+        }
+
+        // Only the lengths of the local header are needed, the sizes may be
+        // in a data descriptor after the data.
+        self.reader.seek(SeekFrom::Start(entry.header_offset))?;
+        let mut header = [0_u8; LOCAL_HEADER_LEN];
+        self.reader.read_exact(&mut header)?;
+        if (&header[..4]).read_u32::<LittleEndian>()? != LOCAL_HEADER_SIGNATURE {
+            bail!("invalid local header signature");
+        }
+        let name_len = u16::from_le_bytes([header[26], header[27]]) as i64;
+        let extra_len = u16::from_le_bytes([header[28], header[29]]) as i64;
+        self.reader.seek(SeekFrom::Current(name_len + extra_len))?;
+
+        let data = self.reader.by_ref().take(entry.compressed_size);
+        let block_decoder: Box<dyn BlockDecoder> = match entry.method {
+            ZipMethod::Deflate64 => Box::new(Deflate64),
+            _ => Box::new(Deflate),
+        };
+        let data = match entry.method {
+            ZipMethod::Stored => EntryData::Stored(data),
+            _ => EntryData::Deflate(Box::new(Decoder::with_block_decoder(
+                BufReader::new(data),
+                Format::Raw,
+                block_decoder,
+            ))),
+        };
+        Ok(ZipEntryReader {
+            data,
+            checksum: Some(Crc32::new()),
+            size: 0,
+            expected_size: entry.uncompressed_size,
+            expected_crc32: entry.crc32,
+        })
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+enum EntryData<'a, R> {
+    Stored(Take<&'a mut R>),
+    Deflate(Box<Decoder<BufReader<Take<&'a mut R>>>>),
+}
+
+/// Decompressed data of a zip entry, see `ZipArchive::open`.
+pub struct ZipEntryReader<'a, R> {
+    data: EntryData<'a, R>,
+    checksum: Option<Crc32>,
+    size: u64,
+    expected_size: u64,
+    expected_crc32: u32,
+}
+
+impl<R: Read> ZipEntryReader<'_, R> {
+    fn size_mismatch(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "size check failed: expected {}, got {}",
+                self.expected_size, self.size
+            ),
+        )
+    }
+}
+
+impl<R: Read> Read for ZipEntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match &mut self.data {
+            EntryData::Stored(data) => data.read(buf)?,
+            EntryData::Deflate(decoder) => decoder.read(buf)?,
+        };
+        self.size += len as u64;
+        if self.size > self.expected_size {
+            return Err(self.size_mismatch());
+        }
+        if let Some(checksum) = &mut self.checksum {
+            checksum.update(&buf[..len]);
+        }
+        if len > 0 || buf.is_empty() {
+            return Ok(len);
+        }
+
+        if let Some(mut checksum) = self.checksum.take() {
+            if self.size != self.expected_size {
+                return Err(self.size_mismatch());
+            }
+            let crc32 = checksum.finish();
+            if crc32 != self.expected_crc32 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    ErrorKind::CrcMismatch {
+                        expected: self.expected_crc32,
+                        actual: crc32,
+                    },
+                ));
+            }
+        }
+        Ok(0)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_with_format;
+    use crate::test_util::sample_data;
+    use byteorder::WriteBytesExt;
+    use std::io::{Cursor, Write};
+
+    /// Write an archive with UTF-8 names, using ZIP64 records and extra fields
+    /// for all the sizes and offsets if `zip64`.
+    fn write_archive(entries: &[(&str, &[u8], ZipMethod)], zip64: bool) -> Result<Vec<u8>> {
+        let (mut archive, mut directory) = (Vec::new(), Vec::new());
+        for &(name, data, method) in entries {
+            let (method, compressed) = match method {
+                // Without matches of 258 bytes, deflate data is also valid Deflate64.
+                ZipMethod::Deflate | ZipMethod::Deflate64 => {
+                    let mut compressed = Vec::new();
+                    compress_with_format(data, &mut compressed, 6, Format::Raw)?;
+                    let method = match method {
+                        ZipMethod::Deflate => 8,
+                        _ => 9,
+                    };
+                    (method, compressed)
+                }
+                _ => (0, data.to_vec()),
+            };
+            let mut checksum = Crc32::new();
+            checksum.update(data);
+            let crc32 = checksum.finish();
+            let offset = archive.len() as u64;
+
+            archive.write_u32::<LittleEndian>(LOCAL_HEADER_SIGNATURE)?;
+            for field in [45, FLAG_UTF8, method, 0, 0x21] {
+                archive.write_u16::<LittleEndian>(field)?;
+            }
+            archive.write_u32::<LittleEndian>(crc32)?;
+            archive.write_u32::<LittleEndian>(compressed.len() as u32)?;
+            archive.write_u32::<LittleEndian>(data.len() as u32)?;
+            archive.write_u16::<LittleEndian>(name.len() as u16)?;
+            archive.write_u16::<LittleEndian>(0)?;
+            archive.write_all(name.as_bytes())?;
+            archive.write_all(&compressed)?;
+
+            let field = |value: u64| if zip64 { u32::MAX } else { value as u32 };
+            directory.write_u32::<LittleEndian>(CENTRAL_HEADER_SIGNATURE)?;
+            for field in [45, 45, FLAG_UTF8, method, 0, 0x21] {
+                directory.write_u16::<LittleEndian>(field)?;
+            }
+            directory.write_u32::<LittleEndian>(crc32)?;
+            directory.write_u32::<LittleEndian>(field(compressed.len() as u64))?;
+            directory.write_u32::<LittleEndian>(field(data.len() as u64))?;
+            directory.write_u16::<LittleEndian>(name.len() as u16)?;
+            directory.write_u16::<LittleEndian>(if zip64 { 28 } else { 0 })?;
+            for field in [0, 0, 0] {
+                directory.write_u16::<LittleEndian>(field)?;
+            }
+            directory.write_u32::<LittleEndian>(0)?;
+            directory.write_u32::<LittleEndian>(field(offset))?;
+            directory.write_all(name.as_bytes())?;
+            if zip64 {
+                directory.write_u16::<LittleEndian>(ZIP64_EXTRA_ID)?;
+                directory.write_u16::<LittleEndian>(24)?;
+                for value in [data.len() as u64, compressed.len() as u64, offset] {
+                    directory.write_u64::<LittleEndian>(value)?;
+                }
+            }
+        }
+
+        let (directory_offset, count) = (archive.len() as u64, entries.len() as u64);
+        archive.write_all(&directory)?;
+        if zip64 {
+            let record_offset = archive.len() as u64;
+            archive.write_u32::<LittleEndian>(ZIP64_END_SIGNATURE)?;
+            archive.write_u64::<LittleEndian>(44)?;
+            archive.write_u16::<LittleEndian>(45)?;
+            archive.write_u16::<LittleEndian>(45)?;
+            archive.write_u64::<LittleEndian>(0)?;
+            for value in [count, count, directory.len() as u64, directory_offset] {
+                archive.write_u64::<LittleEndian>(value)?;
+            }
+            archive.write_u32::<LittleEndian>(ZIP64_LOCATOR_SIGNATURE)?;
+            archive.write_u32::<LittleEndian>(0)?;
+            archive.write_u64::<LittleEndian>(record_offset)?;
+            archive.write_u32::<LittleEndian>(1)?;
+        }
+        let comment = b"comment ending like an end record PK\x05\x06";
+        archive.write_u32::<LittleEndian>(END_SIGNATURE)?;
+        archive.write_u32::<LittleEndian>(0)?;
+        let count = if zip64 { u16::MAX } else { count as u16 };
+        archive.write_u16::<LittleEndian>(count)?;
+        archive.write_u16::<LittleEndian>(count)?;
+        let field = |value: u64| if zip64 { u32::MAX } else { value as u32 };
+        archive.write_u32::<LittleEndian>(field(directory.len() as u64))?;
+        archive.write_u32::<LittleEndian>(field(directory_offset))?;
+        archive.write_u16::<LittleEndian>(comment.len() as u16)?;
+        archive.write_all(comment)?;
+        Ok(archive)
+    }
+
+    #[test]
+    fn read_entries() -> Result<()> {
+        let data = sample_data(300000);
+        let entries: [(&str, &[u8], ZipMethod); 4] = [
+            ("docs/", b"", ZipMethod::Stored),
+            ("docs/readme.txt", b"stored entry", ZipMethod::Stored),
+            ("data.bin", &data, ZipMethod::Deflate),
+            (
+                "enhanced.txt",
+                b"deflate64 entry, deflate64 entry",
+                ZipMethod::Deflate64,
+            ),
+        ];
+        for zip64 in [false, true] {
+            let archive = write_archive(&entries, zip64)?;
+            let mut archive = ZipArchive::new(Cursor::new(archive))?;
+            let names: Vec<&str> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+            assert_eq!(names[..3], ["docs/", "docs/readme.txt", "data.bin"]);
+            assert_eq!(archive.entries()[3].method, ZipMethod::Deflate64);
+            assert!(archive.entries()[0].is_dir());
+            assert_eq!(archive.entries()[2].uncompressed_size, data.len() as u64);
+            assert_eq!(
+                archive.entries()[2].modified.to_string(),
+                "1980-01-01 00:00:00"
+            );
+
+            for (index, &(name, expected, _)) in entries.iter().enumerate() {
+                assert_eq!(archive.index_of(name), Some(index));
+                let mut output = Vec::new();
+                archive.open(index)?.read_to_end(&mut output)?;
+                assert!(output == expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_entry() -> Result<()> {
+        let mut archive = write_archive(&[("a.txt", b"stored entry", ZipMethod::Stored)], false)?;
+        archive[LOCAL_HEADER_LEN + 5] ^= 1;
+        let mut archive = ZipArchive::new(Cursor::new(archive))?;
+        let err = archive.open(0)?.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<ErrorKind>()),
+            Some(ErrorKind::CrcMismatch { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn enclosed_path() {
+        let path = |name: &str| {
+            let entry = ZipEntry {
+                name: name.to_string(),
+                method: ZipMethod::Stored,
+                modified: DosDateTime {
+                    date: 0x21,
+                    time: 0,
+                },
+                crc32: 0,
+                compressed_size: 0,
+                uncompressed_size: 0,
+                is_encrypted: false,
+                header_offset: 0,
+            };
+            entry.enclosed_path()
+        };
+        assert_eq!(path("a/./b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(path("../b.txt"), None);
+        assert_eq!(path("a/../../b.txt"), None);
+        assert_eq!(path("/etc/passwd"), None);
+    }
+}
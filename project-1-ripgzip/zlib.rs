@@ -0,0 +1,101 @@
+#![forbid(unsafe_code)]
+
+use std::io::{BufRead, Write};
+
+use anyhow::{bail, Result};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::ErrorKind;
+
+////////////////////////////////////////////////////////////////////////////////
+
+const CM_DEFLATE: u8 = 8;
+const MAX_CINFO: u8 = 7;
+
+pub const FDICT_OFFSET: u8 = 5;
+const FLEVEL_OFFSET: u8 = 6;
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Zlib stream header, see RFC 1950, section 2.2.
+#[derive(Debug)]
+pub struct ZlibHeader {
+    /// Base-2 logarithm of the LZ77 window size minus eight.
+    pub window_info: u8,
+    /// Compression level hint, from 0 (fastest) to 3 (maximum compression).
+    pub level: u8,
+    /// Adler-32 of the preset dictionary, if one is used.
+    pub dictionary_id: Option<u32>,
+}
+
+impl ZlibHeader {
+    pub fn read<R: BufRead>(reader: &mut R) -> Result<Self> {
+        let (cmf, flg) = (reader.read_u8()?, reader.read_u8()?);
+        if cmf & 0x0f != CM_DEFLATE {
+            bail!(ErrorKind::UnsupportedMethod(cmf & 0x0f));
+        }
+        if cmf >> 4 > MAX_CINFO {
+            bail!(ErrorKind::InvalidHeader("window size"));
+        }
+        if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+            bail!(ErrorKind::InvalidHeader("check bits"));
+        }
+        let dictionary_id = match (flg >> FDICT_OFFSET) & 1 {
+            0 => None,
+            _ => Some(reader.read_u32::<BigEndian>()?),
+        };
+        Ok(Self {
+            window_info: cmf >> 4,
+            level: flg >> FLEVEL_OFFSET,
+            dictionary_id,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let cmf = (self.window_info << 4) | CM_DEFLATE;
+        let mut flg =
+            (self.level << FLEVEL_OFFSET) | ((self.dictionary_id.is_some() as u8) << FDICT_OFFSET);
+        let check = (cmf as u16 * 256 + flg as u16) % 31;
+        flg += ((31 - check) % 31) as u8;
+        writer.write_all(&[cmf, flg])?;
+        if let Some(dictionary_id) = self.dictionary_id {
+            writer.write_u32::<BigEndian>(dictionary_id)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_trailer<R: BufRead>(reader: &mut R) -> Result<u32> {
+    Ok(reader.read_u32::<BigEndian>()?)
+}
+
+pub fn write_trailer<W: Write>(writer: &mut W, adler32: u32) -> Result<()> {
+    Ok(writer.write_u32::<BigEndian>(adler32)?)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header() -> Result<()> {
+        let header = ZlibHeader::read(&mut &[0x78_u8, 0x9c][..])?;
+        assert_eq!(header.window_info, 7);
+        assert_eq!(header.level, 2);
+        assert_eq!(header.dictionary_id, None);
+
+        let header = ZlibHeader::read(&mut &[0x78_u8, 0xbb, 0x01, 0x02, 0x03, 0x04][..])?;
+        assert_eq!(header.dictionary_id, Some(0x01020304));
+
+        let mut written = Vec::new();
+        header.write(&mut written)?;
+        assert_eq!(written, [0x78, 0xbb, 0x01, 0x02, 0x03, 0x04]);
+
+        assert!(ZlibHeader::read(&mut &[0x78_u8, 0x9d][..]).is_err());
+        assert!(ZlibHeader::read(&mut &[0x77_u8, 0x9c][..]).is_err());
+        assert!(ZlibHeader::read(&mut &[0x88_u8, 0x98][..]).is_err());
+        Ok(())
+    }
+}
@@ -22,14 +22,18 @@ pub struct Connection {
 
 impl Connection {
     pub fn open_sqlite_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        Ok(Self {
-            inner: Box::new(rusqlite::Connection::open(path)?),
-        })
+        Self::from_sqlite(rusqlite::Connection::open(path)?)
     }
 
     pub fn open_in_memory() -> Result<Self> {
+        Self::from_sqlite(rusqlite::Connection::open_in_memory()?)
+    }
+
+    fn from_sqlite(connection: rusqlite::Connection) -> Result<Self> {
+        // SQLite checks the constraints of `Ref` fields only when asked to.
+        connection.execute_batch("PRAGMA foreign_keys = ON")?;
         Ok(Self {
-            inner: Box::new(rusqlite::Connection::open_in_memory()?),
+            inner: Box::new(connection),
         })
     }
 
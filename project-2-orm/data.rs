@@ -1,6 +1,7 @@
 #![forbid(unsafe_code)]
 
-use std::{borrow::Cow, fmt};
+use crate::object::{Object, Schema};
+use std::{borrow::Cow, fmt, marker::PhantomData};
 
 ////////////////////////////////////////////////////////////////////////////////
 
@@ -30,6 +31,57 @@ impl ObjectId {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+/// A reference to an object of type `T`, stored as its id with a foreign key
+/// constraint. The constraint is checked on commit, so an object can't be
+/// deleted while others still refer to it.
+pub struct Ref<T> {
+    id: ObjectId,
+    object_type: PhantomData<T>,
+}
+
+impl<T> Ref<T> {
+    pub fn new(id: ObjectId) -> Self {
+        Self {
+            id,
+            object_type: PhantomData,
+        }
+    }
+
+    pub fn id(&self) -> ObjectId {
+        self.id
+    }
+}
+
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Ref<T> {}
+
+impl<T> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Ref({})", self.id)
+    }
+}
+
+impl<T> From<ObjectId> for Ref<T> {
+    fn from(id: ObjectId) -> Self {
+        Self::new(id)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum DataType {
     String,
@@ -41,6 +93,8 @@ pub enum DataType {
 
 pub trait ObjectColumnType {
     const NAME: DataType;
+    const NULLABLE: bool = false;
+    const REFERENCES: Option<fn() -> &'static Schema> = None;
 }
 
 impl ObjectColumnType for i64 {
@@ -62,6 +116,23 @@ impl ObjectColumnType for Vec<u8> {
 impl ObjectColumnType for bool {
     const NAME: DataType = DataType::Bool;
 }
+
+impl<T: Object> ObjectColumnType for Ref<T> {
+    const NAME: DataType = DataType::Int64;
+    const REFERENCES: Option<fn() -> &'static Schema> = Some(schema_of::<T>);
+}
+
+// A function rather than `T::SCHEMA` itself, so that an object may refer to its
+// own type without the schema depending on itself.
+fn schema_of<T: Object>() -> &'static Schema {
+    T::SCHEMA
+}
+
+impl<T: ObjectColumnType> ObjectColumnType for Option<T> {
+    const NAME: DataType = T::NAME;
+    const NULLABLE: bool = true;
+    const REFERENCES: Option<fn() -> &'static Schema> = T::REFERENCES;
+}
 ////////////////////////////////////////////////////////////////////////////////
 
 pub enum Value<'a> {
@@ -70,6 +141,7 @@ pub enum Value<'a> {
     Int64(i64),
     Float64(f64),
     Bool(bool),
+    Null,
 }
 
 impl<'a> Value<'a> {
@@ -80,6 +152,19 @@ impl<'a> Value<'a> {
             Value::String(string) => string,
             Value::Bytes(bytes) => bytes,
             Value::Bool(b) => b,
+            Value::Null => &rusqlite::types::Null,
+        }
+    }
+
+    /// The same value, with borrowed strings and bytes copied.
+    pub fn into_owned(self) -> Value<'static> {
+        match self {
+            Value::String(string) => Value::String(Cow::Owned(string.into_owned())),
+            Value::Bytes(bytes) => Value::Bytes(Cow::Owned(bytes.into_owned())),
+            Value::Int64(int) => Value::Int64(int),
+            Value::Float64(float) => Value::Float64(float),
+            Value::Bool(b) => Value::Bool(b),
+            Value::Null => Value::Null,
         }
     }
 }
@@ -88,6 +173,20 @@ pub trait ToSqlRow {
     fn to_sql_row(&self) -> Vec<&dyn rusqlite::ToSql>;
 }
 
+/// Conversion of a column value into a field, `Null` only fits into `Option<T>`.
+pub trait FromValue {
+    fn from_value(value: Value<'_>) -> Self;
+}
+
+impl<T> FromValue for T
+where
+    T: for<'v> From<Value<'v>>,
+{
+    fn from_value(value: Value<'_>) -> Self {
+        value.into()
+    }
+}
+
 // i64 <-> Int64
 
 impl<'a> From<&'a i64> for Value<'static> {
@@ -105,6 +204,12 @@ impl<'a> From<Value<'_>> for i64 {
     }
 }
 
+impl From<i64> for Value<'static> {
+    fn from(x: i64) -> Self {
+        Value::Int64(x)
+    }
+}
+
 // f64 <-> Float64
 
 impl<'a> From<&'a f64> for Value<'static> {
@@ -122,6 +227,12 @@ impl<'a> From<Value<'_>> for f64 {
     }
 }
 
+impl From<f64> for Value<'static> {
+    fn from(x: f64) -> Self {
+        Value::Float64(x)
+    }
+}
+
 // String <-> Value::String
 
 impl<'a> From<&'a String> for Value<'a> {
@@ -130,6 +241,18 @@ impl<'a> From<&'a String> for Value<'a> {
     }
 }
 
+impl<'a> From<&'a str> for Value<'a> {
+    fn from(string: &'a str) -> Self {
+        Value::String(string.into())
+    }
+}
+
+impl From<String> for Value<'static> {
+    fn from(string: String) -> Self {
+        Value::String(string.into())
+    }
+}
+
 impl<'a> From<Value<'_>> for String {
     fn from(x: Value<'_>) -> Self {
         match x {
@@ -147,6 +270,12 @@ impl<'a> From<&'a Vec<u8>> for Value<'a> {
     }
 }
 
+impl From<Vec<u8>> for Value<'static> {
+    fn from(bytes: Vec<u8>) -> Self {
+        Value::Bytes(bytes.into())
+    }
+}
+
 impl<'a> From<Value<'_>> for Vec<u8> {
     fn from(x: Value<'_>) -> Self {
         match x {
@@ -166,6 +295,12 @@ impl<'a> From<&'a bool> for Value<'static> {
     }
 }
 
+impl From<bool> for Value<'static> {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
 impl<'a> From<Value<'_>> for bool {
     fn from(x: Value<'_>) -> Self {
         match x {
@@ -174,3 +309,52 @@ impl<'a> From<Value<'_>> for bool {
         }
     }
 }
+
+// ObjectId, Ref<T> <-> Int64
+
+impl From<ObjectId> for Value<'static> {
+    fn from(id: ObjectId) -> Self {
+        Value::Int64(id.into_i64())
+    }
+}
+
+impl<'a, T> From<&'a Ref<T>> for Value<'static> {
+    fn from(reference: &'a Ref<T>) -> Self {
+        reference.id().into()
+    }
+}
+
+impl<T> From<Ref<T>> for Value<'static> {
+    fn from(reference: Ref<T>) -> Self {
+        reference.id().into()
+    }
+}
+
+impl<T> From<Value<'_>> for Ref<T> {
+    fn from(x: Value<'_>) -> Self {
+        Ref::new(ObjectId::from(i64::from(x)))
+    }
+}
+
+// Option<T> <-> Null or the value of T
+
+impl<'a, 'b, T> From<&'a Option<T>> for Value<'b>
+where
+    &'a T: Into<Value<'b>>,
+{
+    fn from(x: &'a Option<T>) -> Self {
+        match x {
+            Some(y) => y.into(),
+            None => Value::Null,
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value<'_>) -> Self {
+        match value {
+            Value::Null => None,
+            value => Some(T::from_value(value)),
+        }
+    }
+}
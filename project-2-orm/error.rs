@@ -1,5 +1,5 @@
 #![forbid(unsafe_code)]
-use crate::Error::{LockConflict, Storage};
+use crate::Error::{ForeignKeyViolation, LockConflict, Storage};
 use crate::{data::DataType, object::Schema, ObjectId};
 use thiserror::Error;
 
@@ -13,8 +13,16 @@ pub enum Error {
     UnexpectedType(Box<UnexpectedTypeError>),
     #[error(transparent)]
     MissingColumn(Box<MissingColumnError>),
+    #[error(transparent)]
+    UnknownColumn(Box<UnknownColumnError>),
+    #[error(transparent)]
+    NotReference(Box<NotReferenceError>),
+    #[error(transparent)]
+    BorrowedObject(Box<BorrowedObjectError>),
     #[error("database is locked")]
     LockConflict,
+    #[error("a reference points to an object that doesn't exist")]
+    ForeignKeyViolation,
     #[error("storage error: {0}")]
     Storage(#[source] Box<dyn std::error::Error>),
 }
@@ -105,16 +113,78 @@ impl MissingColumnError {
 
 ////////////////////////////////////////////////////////////////////////////////
 
+#[derive(Error, Debug)]
+#[error("unknown column '{column_name}' in a query of type '{type_name}'")]
+pub struct UnknownColumnError {
+    pub type_name: &'static str,
+    pub column_name: String,
+}
+
+impl UnknownColumnError {
+    pub fn new(type_name: &'static str, column_name: String) -> Self {
+        Self {
+            type_name,
+            column_name,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("{type_name}::{attr_name} is not a reference to {referenced_type}")]
+pub struct NotReferenceError {
+    pub type_name: &'static str,
+    pub attr_name: &'static str,
+    pub referenced_type: &'static str,
+}
+
+impl NotReferenceError {
+    pub fn new(
+        type_name: &'static str,
+        attr_name: &'static str,
+        referenced_type: &'static str,
+    ) -> Self {
+        Self {
+            type_name,
+            attr_name,
+            referenced_type,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+#[error("object is borrowed mutably: type '{type_name}', id {object_id}")]
+pub struct BorrowedObjectError {
+    pub object_id: ObjectId,
+    pub type_name: &'static str,
+}
+
+impl BorrowedObjectError {
+    pub fn new(object_id: ObjectId, type_name: &'static str) -> Self {
+        Self {
+            object_id,
+            type_name,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 impl From<rusqlite::Error> for Error {
     fn from(error: rusqlite::Error) -> Self {
         match error {
             rusqlite::Error::SqliteFailure(err, _y) => {
-                if err.code != rusqlite::ErrorCode::DatabaseBusy {
-                    Storage(Box::new(err))
-                } else {
+                if err.code == rusqlite::ErrorCode::DatabaseBusy {
                     LockConflict
+                } else if err.extended_code == rusqlite::ffi::SQLITE_CONSTRAINT_FOREIGNKEY {
+                    ForeignKeyViolation
+                } else {
+                    Storage(Box::new(err))
                 }
             }
             other => Storage(Box::new(other)),
@@ -7,12 +7,14 @@ mod transaction;
 
 pub mod data;
 pub mod object;
+pub mod query;
 pub mod storage;
 
 pub use connection::Connection;
-pub use data::ObjectId;
+pub use data::{ObjectId, Ref};
 pub use error::{Error, Result};
 pub use object::Object;
+pub use query::{column, Select};
 pub use transaction::{ObjectState, Transaction, Tx};
 
 pub use orm_derive::Object;
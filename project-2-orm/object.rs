@@ -14,6 +14,8 @@ pub struct ColumnInfo {
     pub data_name: &'static str,
     pub data_type: DataType,
     pub column_name: &'static str,
+    pub nullable: bool,
+    pub references: Option<fn() -> &'static Schema>,
 }
 
 impl Schema {
@@ -30,6 +32,19 @@ impl Schema {
             end_part.push_str(data.column_name);
             end_part.push(' ');
             end_part.push_str(data.data_name);
+            if !data.nullable {
+                end_part.push_str(" NOT NULL");
+            }
+        }
+        for data in self.info {
+            if let Some(schema) = data.references {
+                // Deferred, so that objects may be saved and deleted in any order.
+                end_part.push_str(&format!(
+                    ", FOREIGN KEY({}) REFERENCES {}(id) DEFERRABLE INITIALLY DEFERRED",
+                    data.column_name,
+                    schema().table_name
+                ));
+            }
         }
         common_part.push_str(end_part.as_str());
         common_part.push(')');
@@ -87,6 +102,17 @@ impl Schema {
         query_str
     }
 
+    pub fn make_select_all_query_str(&self) -> String {
+        let mut query_str = "SELECT id".to_string();
+        for data in self.info {
+            query_str.push(',');
+            query_str.push_str(data.column_name);
+        }
+        query_str.push_str(" FROM ");
+        query_str.push_str(self.table_name);
+        query_str
+    }
+
     pub fn make_delete_query_str(&self) -> String {
         format!("DELETE FROM {} WHERE id = ?", self.table_name)
     }
@@ -51,6 +51,8 @@ fn create_code(ident:Ident,
                     data_name: stringify!(#fields_names),
                     data_type: <#types_names as ::orm::data::ObjectColumnType>::NAME,
                     column_name: #column_names,
+                    nullable: <#types_names as ::orm::data::ObjectColumnType>::NULLABLE,
+                    references: <#types_names as ::orm::data::ObjectColumnType>::REFERENCES,
                     },)*],
             };
 
@@ -65,7 +67,7 @@ fn create_code(ident:Ident,
 
             fn get_object_from_row(row: ::orm::storage::Row) -> Self {
                 let mut iter = row.into_iter();
-                Self { #(#fields_names: ::std::convert::From::from(iter.next().unwrap())), *}
+                Self { #(#fields_names: ::orm::data::FromValue::from_value(iter.next().unwrap())), *}
             }
         }
     };
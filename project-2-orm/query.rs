@@ -0,0 +1,178 @@
+#![forbid(unsafe_code)]
+use crate::{
+    data::Value,
+    error::{Error, Result, UnknownColumnError},
+    object::{ColumnInfo, Object, Schema},
+    storage::Row,
+    transaction::{Transaction, Tx},
+};
+use std::marker::PhantomData;
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn to_sql(self) -> &'static str {
+        match self {
+            Operator::Eq => "=",
+            Operator::Ne => "<>",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+        }
+    }
+}
+
+/// A column of a query, named by its field or column name.
+pub struct Column {
+    name: String,
+}
+
+pub fn column(name: &str) -> Column {
+    Column {
+        name: name.to_string(),
+    }
+}
+
+impl Column {
+    fn compare<'v>(self, operator: Operator, value: impl Into<Value<'v>>) -> Predicate {
+        Predicate {
+            column: self.name,
+            operator,
+            value: value.into().into_owned(),
+        }
+    }
+
+    pub fn eq<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Eq, value)
+    }
+
+    pub fn ne<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Ne, value)
+    }
+
+    pub fn lt<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Lt, value)
+    }
+
+    pub fn le<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Le, value)
+    }
+
+    pub fn gt<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Gt, value)
+    }
+
+    pub fn ge<'v>(self, value: impl Into<Value<'v>>) -> Predicate {
+        self.compare(Operator::Ge, value)
+    }
+}
+
+/// A comparison of a column with a value, see `column`.
+pub struct Predicate {
+    column: String,
+    operator: Operator,
+    value: Value<'static>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A query of the objects of type `T`, see `Transaction::select`. The changes
+/// made in the transaction are written to the storage first, which evaluates
+/// the predicates.
+pub struct Select<'t, 'a, T> {
+    transaction: &'t Transaction<'a>,
+    predicates: Vec<Predicate>,
+    order: Vec<(String, bool)>,
+    limit: Option<u64>,
+    object_type: PhantomData<T>,
+}
+
+impl<'t, 'a, T: Object> Select<'t, 'a, T> {
+    pub(crate) fn new(transaction: &'t Transaction<'a>) -> Self {
+        Self {
+            transaction,
+            predicates: Vec::new(),
+            order: Vec::new(),
+            limit: None,
+            object_type: PhantomData,
+        }
+    }
+
+    /// Also require `predicate`, in addition to the previous filters.
+    pub fn filter(mut self, predicate: Predicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+
+    pub fn order_by(mut self, column: &str) -> Self {
+        self.order.push((column.to_string(), false));
+        self
+    }
+
+    pub fn order_by_desc(mut self, column: &str) -> Self {
+        self.order.push((column.to_string(), true));
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn fetch(self) -> Result<Vec<Tx<'t, T>>> {
+        let query = self.make_query_str()?;
+        let params: Row = self
+            .predicates
+            .into_iter()
+            .map(|predicate| predicate.value)
+            .collect();
+        self.transaction.fetch::<T>(query.as_str(), &params)
+    }
+
+    fn make_query_str(&self) -> Result<String> {
+        let schema = T::SCHEMA;
+        let mut query_str = schema.make_select_all_query_str();
+        for (i, predicate) in self.predicates.iter().enumerate() {
+            query_str.push_str(if i == 0 { " WHERE " } else { " AND " });
+            query_str.push_str(find_column(schema, &predicate.column)?.column_name);
+            query_str.push(' ');
+            query_str.push_str(predicate.operator.to_sql());
+            query_str.push_str(" ?");
+        }
+        for (i, (column, is_desc)) in self.order.iter().enumerate() {
+            query_str.push_str(if i == 0 { " ORDER BY " } else { ", " });
+            query_str.push_str(find_column(schema, column)?.column_name);
+            query_str.push_str(if *is_desc { " DESC" } else { " ASC" });
+        }
+        if let Some(limit) = self.limit {
+            query_str.push_str(&format!(" LIMIT {}", limit));
+        }
+        Ok(query_str)
+    }
+}
+
+/// Only names from the schema get into the query, the values are parameters.
+pub(crate) fn find_column(schema: &'static Schema, name: &str) -> Result<&'static ColumnInfo> {
+    schema
+        .info
+        .iter()
+        .find(|info| info.data_name == name)
+        .or_else(|| schema.info.iter().find(|info| info.column_name == name))
+        .ok_or_else(|| {
+            Error::UnknownColumn(Box::new(UnknownColumnError::new(
+                schema.type_name,
+                name.to_string(),
+            )))
+        })
+}
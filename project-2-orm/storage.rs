@@ -7,7 +7,11 @@ use crate::{
     object::Schema,
     ObjectId,
 };
-use rusqlite::{params, params_from_iter, ToSql};
+use rusqlite::{
+    params, params_from_iter,
+    types::{Null, ValueRef},
+    ToSql,
+};
 use std::borrow::Cow;
 use crate::data::ToSqlRow;
 
@@ -26,12 +30,44 @@ impl<'a> ToSqlRow for RowSlice<'a> {
                 Value::String(string) => string,
                 Value::Bytes(bytes) => bytes,
                 Value::Bool(b) => b,
+                Value::Null => &Null,
             });
         }
         row
     }
 }
 
+/// Read the values of the columns of `schema`, which start at `offset`.
+fn read_row(row: &rusqlite::Row, schema: &Schema, offset: usize) -> rusqlite::Result<Row<'static>> {
+    let mut result_row = Vec::new();
+    for i in 0..schema.info.len() {
+        let index = offset + i;
+        if schema.info[i].nullable && row.get_ref(index)? == ValueRef::Null {
+            result_row.push(Value::Null);
+            continue;
+        }
+        result_row.push(match schema.info[i].data_type {
+            DataType::Int64 => Value::Int64(row.get(index)?),
+            DataType::Float64 => Value::Float64(row.get(index)?),
+            DataType::String => Value::String(Cow::Owned(row.get(index)?)),
+            DataType::Bytes => Value::Bytes(Cow::Owned(row.get(index)?)),
+            DataType::Bool => Value::Bool(row.get::<_, i64>(index)? > 0),
+        });
+    }
+    Ok(result_row)
+}
+
+fn unexpected_type_error(schema: &Schema, i: usize, got_type: String) -> Error {
+    UnexpectedType(Box::new(UnexpectedTypeError::new(
+        schema.type_name,
+        schema.info[i].data_name,
+        schema.table_name,
+        schema.info[i].column_name,
+        schema.info[i].data_type,
+        got_type,
+    )))
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 pub(crate) trait StorageTransaction {
@@ -41,6 +77,12 @@ pub(crate) trait StorageTransaction {
     fn insert_row(&self, schema: &Schema, row: &RowSlice) -> Result<ObjectId>;
     fn update_row(&self, id: ObjectId, schema: &Schema, row: &RowSlice) -> Result<()>;
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>>;
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>>;
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()>;
 
     fn commit(&self) -> Result<()>;
@@ -68,12 +110,12 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
                 params_from_iter(row.to_sql_row().into_iter()),
             );
 
-            if res.is_err() {
-                if let Some(error) =
-                    get_missing_column_error(res.err().unwrap().to_string().as_str(), schema)
-                {
+            if let Err(err) = res {
+                if let Some(error) = get_missing_column_error(err.to_string().as_str(), schema) {
                     return Err(MissingColumn(Box::new(error)));
                 }
+                // E.g. a null in a NOT NULL column.
+                return Err(err.into());
             }
         }
 
@@ -96,19 +138,7 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
     fn select_row(&self, id: ObjectId, schema: &Schema) -> Result<Row<'static>> {
         let query = self.prepare(schema.make_select_query_str().as_str());
         let res = if let Ok(mut query_res) = query {
-            query_res.query_row(params![id.into_i64()], |row| {
-                let mut result_row = Vec::new();
-                for i in 0..schema.info.len() {
-                    result_row.push(match schema.info[i].data_type {
-                        DataType::Int64 => Value::Int64(row.get(i)?),
-                        DataType::Float64 => Value::Float64(row.get(i)?),
-                        DataType::String => Value::String(Cow::Owned(row.get(i)?)),
-                        DataType::Bytes => Value::Bytes(Cow::Owned(row.get(i)?)),
-                        DataType::Bool => Value::Bool(row.get::<_, i64>(i)? > 0),
-                    });
-                }
-                Ok(result_row)
-            })
+            query_res.query_row(params![id.into_i64()], |row| read_row(row, schema, 0))
         } else {
             Err(query.err().unwrap())
         };
@@ -119,14 +149,7 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
 
         match res.err().unwrap() {
             rusqlite::Error::InvalidColumnType(i, _name, type_n) => {
-                return Err(UnexpectedType(Box::new(UnexpectedTypeError::new(
-                    schema.type_name,
-                    schema.info[i].data_name,
-                    schema.table_name,
-                    schema.info[i].column_name,
-                    schema.info[i].data_type,
-                    type_n.to_string(),
-                ))));
+                return Err(unexpected_type_error(schema, i, type_n.to_string()));
             }
             rusqlite::Error::SqliteFailure(_e, text) => {
                 return Err(MissingColumn(Box::new(
@@ -138,6 +161,37 @@ impl<'a> StorageTransaction for rusqlite::Transaction<'a> {
         Err(NotFound(Box::new(NotFoundError::new(id, schema.type_name))))
     }
 
+    fn select_rows(
+        &self,
+        schema: &Schema,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<(ObjectId, Row<'static>)>> {
+        let convert_error = |error: rusqlite::Error| match error {
+            // The id comes before the columns of the schema.
+            rusqlite::Error::InvalidColumnType(i, _name, type_n) if i > 0 => {
+                unexpected_type_error(schema, i - 1, type_n.to_string())
+            }
+            rusqlite::Error::SqliteFailure(err, Some(text)) => {
+                match get_missing_column_error(text.as_str(), schema) {
+                    Some(error) => MissingColumn(Box::new(error)),
+                    None => Error::from(rusqlite::Error::SqliteFailure(err, Some(text))),
+                }
+            }
+            other => Error::from(other),
+        };
+
+        let mut statement = self.prepare(query).map_err(convert_error)?;
+        let rows = statement
+            .query_map(params_from_iter(params.to_sql_row()), |row| {
+                let id = ObjectId::from(row.get::<_, i64>(0)?);
+                Ok((id, read_row(row, schema, 1)?))
+            })
+            .map_err(convert_error)?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(convert_error)
+    }
+
     fn delete_row(&self, id: ObjectId, schema: &Schema) -> Result<()> {
         if self
             .execute(
@@ -0,0 +1,104 @@
+#![forbid(unsafe_code)]
+use orm::{column, data::Value, Connection, Error, Object, Result};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object)]
+#[table_name("people")]
+struct Person {
+    name: String,
+    nickname: Option<String>,
+    age: Option<i64>,
+    photo: Option<Vec<u8>>,
+}
+
+#[derive(Object)]
+#[table_name("accounts")]
+struct Account {
+    login: String,
+}
+
+#[derive(Object)]
+#[table_name("accounts")]
+struct MaybeAccount {
+    login: Option<String>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn round_trip() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let empty = tx
+        .create(Person {
+            name: "empty".to_string(),
+            nickname: None,
+            age: None,
+            photo: None,
+        })?
+        .id();
+    let full = tx
+        .create(Person {
+            name: "full".to_string(),
+            nickname: Some("f".to_string()),
+            age: Some(0),
+            photo: Some(vec![]),
+        })?
+        .id();
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    let person = tx.get::<Person>(empty)?;
+    assert_eq!(person.borrow().nickname, None);
+    assert_eq!(person.borrow().age, None);
+    assert_eq!(person.borrow().photo, None);
+    person.borrow_mut().age = Some(20);
+
+    let person = tx.get::<Person>(full)?;
+    assert_eq!(person.borrow().nickname.as_deref(), Some("f"));
+    assert_eq!(person.borrow().age, Some(0));
+    assert_eq!(person.borrow().photo, Some(vec![]));
+    person.borrow_mut().nickname = None;
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    assert_eq!(tx.get::<Person>(empty)?.borrow().age, Some(20));
+    assert_eq!(tx.get::<Person>(full)?.borrow().nickname, None);
+
+    // Null is never equal to anything, including a null value.
+    let people = tx
+        .select::<Person>()
+        .filter(column("nickname").eq(Value::Null))
+        .fetch()?;
+    assert!(people.is_empty());
+    let people = tx.select::<Person>().filter(column("age").gt(10)).fetch()?;
+    assert_eq!(people.len(), 1);
+    assert_eq!(people[0].id(), empty);
+    Ok(())
+}
+
+#[test]
+fn not_null() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    tx.create(Account {
+        login: "root".to_string(),
+    })?;
+    tx.commit()?;
+
+    // The table created for Account doesn't accept nulls.
+    let tx = conn.new_transaction()?;
+    tx.create(MaybeAccount {
+        login: Some("admin".to_string()),
+    })?;
+    let err = tx.create(MaybeAccount { login: None }).err().unwrap();
+    assert!(matches!(err, Error::Storage(_)));
+    tx.rollback()?;
+
+    let tx = conn.new_transaction()?;
+    let accounts = tx.select::<Account>().fetch()?;
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].borrow().login, "root");
+    Ok(())
+}
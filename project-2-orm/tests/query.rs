@@ -0,0 +1,186 @@
+#![forbid(unsafe_code)]
+use orm::{column, Connection, Error, Object, ObjectState, Result, Transaction};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object)]
+#[table_name("users")]
+struct User {
+    #[column_name("full_name")]
+    name: String,
+    age: i64,
+}
+
+fn create_users(tx: &Transaction) -> Result<()> {
+    for (name, age) in [("alice", 30), ("bob", 25), ("carol", 35), ("o'brien", 40)] {
+        tx.create(User {
+            name: name.to_string(),
+            age,
+        })?;
+    }
+    Ok(())
+}
+
+fn names(users: &[orm::Tx<User>]) -> Vec<String> {
+    users
+        .iter()
+        .map(|user| user.borrow().name.clone())
+        .collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn filter_order_limit() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    create_users(&tx)?;
+
+    let users = tx
+        .select::<User>()
+        .filter(column("age").gt(26))
+        .filter(column("age").le(35))
+        .order_by("name")
+        .fetch()?;
+    assert_eq!(names(&users), ["alice", "carol"]);
+
+    let users = tx.select::<User>().order_by_desc("age").limit(2).fetch()?;
+    assert_eq!(names(&users), ["o'brien", "carol"]);
+
+    let users = tx
+        .select::<User>()
+        .filter(column("name").ne("bob"))
+        .filter(column("age").lt(40))
+        .order_by("age")
+        .fetch()?;
+    assert_eq!(names(&users), ["alice", "carol"]);
+
+    // Values are passed as parameters rather than pasted into the query.
+    let name = String::from("o'brien");
+    let users = tx
+        .select::<User>()
+        .filter(column("name").eq(name.as_str()))
+        .fetch()?;
+    assert_eq!(names(&users), ["o'brien"]);
+
+    let users = tx.select::<User>().filter(column("age").ge(100)).fetch()?;
+    assert!(users.is_empty());
+    Ok(())
+}
+
+#[test]
+fn column_names() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    create_users(&tx)?;
+
+    // Columns are named either by the field or by the column.
+    let by_field = tx
+        .select::<User>()
+        .filter(column("name").eq("bob"))
+        .fetch()?;
+    let by_column = tx
+        .select::<User>()
+        .filter(column("full_name").eq("bob"))
+        .fetch()?;
+    assert_eq!(names(&by_field), ["bob"]);
+    assert_eq!(by_field[0].id(), by_column[0].id());
+
+    let err = tx
+        .select::<User>()
+        .filter(column("email").eq("bob@example.com"))
+        .fetch()
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::UnknownColumn(_)));
+    assert_eq!(
+        err.to_string(),
+        "unknown column 'email' in a query of type 'User'"
+    );
+
+    let err = tx
+        .select::<User>()
+        .order_by("age; DROP TABLE users")
+        .fetch();
+    assert!(matches!(err, Err(Error::UnknownColumn(_))));
+    Ok(())
+}
+
+#[test]
+fn identity_map() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let alice = tx.create(User {
+        name: "alice".to_string(),
+        age: 30,
+    })?;
+
+    // Fetched objects are the ones already loaded, with their changes.
+    alice.borrow_mut().age = 31;
+    let users = tx.select::<User>().filter(column("age").eq(31)).fetch()?;
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id(), alice.id());
+    users[0].borrow_mut().name = "alicia".to_string();
+    assert_eq!(alice.borrow().name, "alicia");
+
+    let users = tx.select::<User>().filter(column("age").eq(30)).fetch()?;
+    assert!(users.is_empty());
+    Ok(())
+}
+
+#[test]
+fn pending_changes() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    create_users(&tx)?;
+    let users = tx.select::<User>().order_by("age").fetch()?;
+
+    // The changes are written once, before the next query.
+    users[0].borrow_mut().age = 50;
+    assert!(users[0].state() == ObjectState::Modified);
+    let oldest = tx.select::<User>().order_by_desc("age").limit(1).fetch()?;
+    assert_eq!(oldest[0].id(), users[0].id());
+    assert!(users[0].state() == ObjectState::Clean);
+
+    let bob = users[0].id();
+    tx.get::<User>(bob)?.delete();
+    tx.select::<User>().fetch()?;
+    assert!(matches!(tx.get::<User>(bob), Err(Error::NotFound(_))));
+
+    // An object being changed can't be written yet.
+    let mut alice = users[1].borrow_mut();
+    alice.age += 1;
+    let err = tx.select::<User>().fetch().err().unwrap();
+    assert!(matches!(err, Error::BorrowedObject(_)));
+    drop(alice);
+    let users = tx.select::<User>().filter(column("age").eq(31)).fetch()?;
+    assert_eq!(names(&users), ["alice"]);
+    Ok(())
+}
+
+#[test]
+fn removed_objects() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let young = tx.create(User {
+        name: "bob".to_string(),
+        age: 25,
+    })?;
+    tx.create(User {
+        name: "alice".to_string(),
+        age: 30,
+    })?;
+    young.delete();
+
+    // The limit applies to the objects that are left.
+    let users = tx.select::<User>().order_by("age").limit(1).fetch()?;
+    assert_eq!(names(&users), ["alice"]);
+    let users = tx.select::<User>().fetch()?;
+    assert_eq!(names(&users), ["alice"]);
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    let users = tx.select::<User>().fetch()?;
+    assert_eq!(names(&users), ["alice"]);
+    Ok(())
+}
@@ -0,0 +1,184 @@
+#![forbid(unsafe_code)]
+use orm::{Connection, Error, Object, ObjectId, Ref, Result};
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Object)]
+struct User {
+    name: String,
+}
+
+#[derive(Object)]
+struct Post {
+    title: String,
+    #[column_name("author_id")]
+    author: Ref<User>,
+    editor: Option<Ref<User>>,
+}
+
+#[derive(Object)]
+struct Category {
+    name: String,
+    parent: Option<Ref<Category>>,
+}
+
+fn create_user<'t>(tx: &'t orm::Transaction, name: &str) -> Result<orm::Tx<'t, User>> {
+    tx.create(User {
+        name: name.to_string(),
+    })
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[test]
+fn resolve() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let alice = create_user(&tx, "alice")?;
+    let post = tx.create(Post {
+        title: "hello".to_string(),
+        author: alice.to_ref(),
+        editor: None,
+    })?;
+    assert_eq!(post.borrow().author, alice.to_ref());
+
+    // References are loaded through the identity map of the transaction.
+    let author = post.get_ref(|post| post.author)?;
+    author.borrow_mut().name = "alicia".to_string();
+    assert_eq!(alice.borrow().name, "alicia");
+    assert_eq!(tx.resolve(&post.borrow().author)?.borrow().name, "alicia");
+    let (post_id, alice_id) = (post.id(), alice.id());
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    let post = tx.get::<Post>(post_id)?;
+    assert_eq!(post.borrow().author.id(), alice_id);
+    assert_eq!(post.borrow().editor, None);
+    let author = post.get_ref(|post| post.author)?;
+    assert_eq!(author.borrow().name, "alicia");
+    assert_eq!(tx.get::<User>(alice_id)?.borrow().name, "alicia");
+    Ok(())
+}
+
+#[test]
+fn children() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let alice = create_user(&tx, "alice")?;
+    let bob = create_user(&tx, "bob")?;
+    for (title, author) in [("b", &alice), ("c", &bob), ("a", &alice)] {
+        tx.create(Post {
+            title: title.to_string(),
+            author: author.to_ref(),
+            editor: Some(bob.to_ref()),
+        })?;
+    }
+
+    let posts = tx
+        .children::<Post, User>("author", &alice)?
+        .order_by("title")
+        .fetch()?;
+    let titles: Vec<_> = posts
+        .iter()
+        .map(|post| post.borrow().title.clone())
+        .collect();
+    assert_eq!(titles, ["a", "b"]);
+    assert_eq!(
+        tx.children::<Post, User>("author_id", &bob)?.fetch()?.len(),
+        1
+    );
+    assert_eq!(tx.children::<Post, User>("editor", &bob)?.fetch()?.len(), 3);
+    assert!(tx
+        .children::<Post, User>("editor", &alice)?
+        .fetch()?
+        .is_empty());
+
+    let err = tx.children::<Post, User>("reviewer", &alice).err().unwrap();
+    assert!(matches!(err, Error::UnknownColumn(_)));
+    let err = tx.children::<Post, User>("title", &alice).err().unwrap();
+    assert!(matches!(err, Error::NotReference(_)));
+    assert_eq!(err.to_string(), "Post::title is not a reference to User");
+    let category = tx.create(Category {
+        name: "news".to_string(),
+        parent: None,
+    })?;
+    let err = tx
+        .children::<Post, Category>("author", &category)
+        .err()
+        .unwrap();
+    assert!(matches!(err, Error::NotReference(_)));
+    Ok(())
+}
+
+#[test]
+fn self_reference() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let root = tx.create(Category {
+        name: "root".to_string(),
+        parent: None,
+    })?;
+    for name in ["first", "second"] {
+        tx.create(Category {
+            name: name.to_string(),
+            parent: Some(root.to_ref()),
+        })?;
+    }
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    let categories = tx.select::<Category>().order_by("name").fetch()?;
+    let names: Vec<_> = categories.iter().map(|c| c.borrow().name.clone()).collect();
+    assert_eq!(names, ["first", "root", "second"]);
+    let root = &categories[1];
+    assert_eq!(root.borrow().parent, None);
+    let children = tx.children::<Category, Category>("parent", root)?.fetch()?;
+    assert_eq!(children.len(), 2);
+    for child in children {
+        let parent = tx.resolve(&child.borrow().parent.unwrap())?;
+        assert_eq!(parent.id(), root.id());
+    }
+    Ok(())
+}
+
+#[test]
+fn foreign_keys() -> Result<()> {
+    let mut conn = Connection::open_in_memory()?;
+    let tx = conn.new_transaction()?;
+    let alice = create_user(&tx, "alice")?;
+    tx.create(Post {
+        title: "hello".to_string(),
+        author: alice.to_ref(),
+        editor: None,
+    })?;
+    let alice_id = alice.id();
+    tx.commit()?;
+
+    // The constraints are checked on commit.
+    let tx = conn.new_transaction()?;
+    tx.create(Post {
+        title: "orphan".to_string(),
+        author: Ref::new(ObjectId::from(100)),
+        editor: None,
+    })?;
+    assert!(matches!(tx.commit(), Err(Error::ForeignKeyViolation)));
+
+    let tx = conn.new_transaction()?;
+    tx.get::<User>(alice_id)?.delete();
+    assert!(matches!(tx.commit(), Err(Error::ForeignKeyViolation)));
+
+    // A reference can be removed along with the object.
+    let tx = conn.new_transaction()?;
+    let user = tx.get::<User>(alice_id)?;
+    assert_eq!(tx.select::<Post>().fetch()?.len(), 1);
+    for post in tx.children::<Post, User>("author", &user)?.fetch()? {
+        post.delete();
+    }
+    user.delete();
+    tx.commit()?;
+
+    let tx = conn.new_transaction()?;
+    assert!(tx.select::<Post>().fetch()?.is_empty());
+    assert!(matches!(tx.get::<User>(alice_id), Err(Error::NotFound(_))));
+    Ok(())
+}
@@ -1,9 +1,10 @@
 #![forbid(unsafe_code)]
 use crate::{
-    data::ObjectId,
-    error::{Error, NotFoundError, Result},
-    object::{Object, Store},
-    storage::StorageTransaction,
+    data::{self, ObjectId},
+    error::{BorrowedObjectError, Error, NotFoundError, NotReferenceError, Result},
+    object::{Object, Schema, Store},
+    query::{column, find_column, Select},
+    storage::{RowSlice, StorageTransaction},
 };
 use std::{
     any::{Any, TypeId},
@@ -17,6 +18,7 @@ use std::{
 
 struct MemoryObject {
     id: ObjectId,
+    schema: &'static Schema,
     state: Cell<ObjectState>,
     object: RefCell<Box<dyn Store>>,
 }
@@ -25,6 +27,7 @@ impl MemoryObject {
     pub fn new(id: ObjectId, state: ObjectState, ptr: Box<dyn Store>) -> Self {
         Self {
             id,
+            schema: ptr.get_schema(),
             state: Cell::new(state),
             object: RefCell::new(ptr),
         }
@@ -49,8 +52,18 @@ impl<'a> Transaction<'a> {
     }
 
     fn ensure_table<T: Object>(&self) -> Result<()> {
-        if !self.inner.table_exists(T::SCHEMA.table_name)? {
-            self.inner.create_table(T::SCHEMA)?;
+        self.ensure_schema(T::SCHEMA)
+    }
+
+    /// Create the table of `schema` along with the tables it refers to.
+    fn ensure_schema(&self, schema: &Schema) -> Result<()> {
+        if !self.inner.table_exists(schema.table_name)? {
+            self.inner.create_table(schema)?;
+            for info in schema.info {
+                if let Some(references) = info.references {
+                    self.ensure_schema(references())?;
+                }
+            }
         }
         Ok(())
     }
@@ -71,7 +84,7 @@ impl<'a> Transaction<'a> {
             .borrow_mut()
             .insert((TypeId::of::<T>(), object_id), memory_object.clone());
 
-        Ok(Tx::new(PhantomData, memory_object))
+        Ok(Tx::new(self, memory_object))
     }
 
     pub fn get<T: Object>(&self, id: ObjectId) -> Result<Tx<'_, T>> {
@@ -86,7 +99,7 @@ impl<'a> Transaction<'a> {
                     ))));
                 }
                 _ => {
-                    return Ok(Tx::new(PhantomData, object));
+                    return Ok(Tx::new(self, object));
                 }
             }
         }
@@ -102,17 +115,93 @@ impl<'a> Transaction<'a> {
             .borrow_mut()
             .insert((TypeId::of::<T>(), id), memory_object.clone());
 
-        Ok(Tx::new(PhantomData, memory_object))
+        Ok(Tx::new(self, memory_object))
+    }
+
+    /// Load the object `reference` points to, see `get`.
+    pub fn resolve<T: Object>(&self, reference: &data::Ref<T>) -> Result<Tx<'_, T>> {
+        self.get(reference.id())
+    }
+
+    /// Start a query of the objects of type `T` whose `Ref<P>` field `field`
+    /// points to `parent`.
+    pub fn children<T: Object, P: Object>(
+        &self,
+        field: &str,
+        parent: &Tx<'_, P>,
+    ) -> Result<Select<'_, 'a, T>> {
+        let info = find_column(T::SCHEMA, field)?;
+        match info.references {
+            Some(references) if references().table_name == P::SCHEMA.table_name => {
+                Ok(self.select::<T>().filter(column(field).eq(parent.id())))
+            }
+            _ => Err(Error::NotReference(Box::new(NotReferenceError::new(
+                T::SCHEMA.type_name,
+                info.data_name,
+                P::SCHEMA.type_name,
+            )))),
+        }
+    }
+
+    /// Start a query of the objects of type `T`, e.g.
+    /// `tx.select::<User>().filter(column("name").eq("alice")).fetch()`.
+    pub fn select<T: Object>(&self) -> Select<'_, 'a, T> {
+        Select::new(self)
+    }
+
+    /// Run a query built by `Select`. Objects already loaded in the transaction
+    /// are shared instead of being read again.
+    pub(crate) fn fetch<T: Object>(
+        &self,
+        query: &str,
+        params: &RowSlice,
+    ) -> Result<Vec<Tx<'_, T>>> {
+        self.ensure_table::<T>()?;
+        // The query runs on the stored rows, which must include the changes
+        // made so far, e.g. for LIMIT to count only objects that still exist.
+        self.try_apply()?;
+        let rows = self.inner.select_rows(T::SCHEMA, query, params)?;
+
+        let mut map = self.map.borrow_mut();
+        let mut objects = Vec::with_capacity(rows.len());
+        for (id, row) in rows {
+            let memory_object = map
+                .entry((TypeId::of::<T>(), id))
+                .or_insert_with(|| {
+                    Rc::new(MemoryObject::new(
+                        id,
+                        ObjectState::Clean,
+                        Box::new(T::get_object_from_row(row)),
+                    ))
+                })
+                .clone();
+            objects.push(Tx::new(self, memory_object));
+        }
+        Ok(objects)
     }
 
+    /// Write the changes made since the last call, which leaves the modified
+    /// objects clean and forgets the removed ones.
     fn try_apply(&self) -> Result<()> {
-        for memory_object in self.map.borrow().values() {
-            let object = memory_object.object.borrow();
-            match memory_object.state.get() {
+        let mut removed = Vec::new();
+        for (key, memory_object) in self.map.borrow().iter() {
+            let state = memory_object.state.get();
+            if state == ObjectState::Clean {
+                continue;
+            }
+            // An object borrowed mutably may still be changing.
+            let object = memory_object.object.try_borrow().map_err(|_| {
+                Error::BorrowedObject(Box::new(BorrowedObjectError::new(
+                    memory_object.id,
+                    memory_object.schema.type_name,
+                )))
+            })?;
+            match state {
                 ObjectState::Clean => {}
                 ObjectState::Removed => {
                     self.inner
                         .delete_row(memory_object.id, object.get_schema())?;
+                    removed.push(*key);
                 }
                 ObjectState::Modified => {
                     self.inner.update_row(
@@ -120,9 +209,15 @@ impl<'a> Transaction<'a> {
                         object.get_schema(),
                         &object.get_row_from_store(),
                     )?;
+                    memory_object.state.set(ObjectState::Clean);
                 }
             }
         }
+        // From now on, the storage reports them as not found.
+        let mut map = self.map.borrow_mut();
+        for key in removed {
+            map.remove(&key);
+        }
         Ok(())
     }
 
@@ -148,12 +243,17 @@ pub enum ObjectState {
 #[derive(Clone)]
 pub struct Tx<'a, T> {
     lifetime: PhantomData<&'a T>,
+    transaction: &'a Transaction<'a>,
     object: Rc<MemoryObject>,
 }
 
 impl<'a, T: Any> Tx<'a, T> {
-    fn new(lifetime: PhantomData<&'a T>, object: Rc<MemoryObject>) -> Self {
-        Self { lifetime, object }
+    fn new(transaction: &'a Transaction<'a>, object: Rc<MemoryObject>) -> Self {
+        Self {
+            lifetime: PhantomData,
+            transaction,
+            object,
+        }
     }
 
     pub fn id(&self) -> ObjectId {
@@ -164,6 +264,21 @@ impl<'a, T: Any> Tx<'a, T> {
         self.object.get_state()
     }
 
+    /// A reference to this object, to be stored in a `Ref<T>` field.
+    pub fn to_ref(&self) -> data::Ref<T> {
+        data::Ref::new(self.id())
+    }
+
+    /// Load the object referred to by the `Ref` field selected by `field`,
+    /// e.g. `post.get_ref(|post| post.author)`, see `Transaction::resolve`.
+    pub fn get_ref<U: Object>(
+        &self,
+        field: impl FnOnce(&T) -> data::Ref<U>,
+    ) -> Result<Tx<'a, U>> {
+        let reference = field(&self.borrow());
+        self.transaction.resolve(&reference)
+    }
+
     pub fn borrow(&self) -> Ref<'_, T> {
         match self.object.get_state() {
             ObjectState::Removed => {